@@ -1,28 +1,113 @@
-use std::net::SocketAddr;
+use std::fmt;
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
-use axum::routing::IntoMakeService;
-use axum::{Extension, Router, Server};
+use axum::{Extension, Router};
 use dyn_clonable::clonable;
-use hyper::server::conn::AddrIncoming;
+use futures::future::{try_join_all, FutureExt};
+use tokio::sync::{watch, Notify};
 
-use crate::configuration::{self, Configuration};
+use crate::configuration::{self, Configuration, ListenAddress};
+use crate::database::entity::local_credential::LocalCredential;
 use crate::database::init_connection;
 
 use crate::driver::db;
+use crate::driver::oidc::OidcMetadataCache;
+use crate::driver::revocation::RevocationList;
+use crate::health::CheckResult;
+use crate::keys::KeySet;
+use crate::metrics::Metrics;
 use crate::middleware::authentication::JwtClaims;
 use crate::routes::{self, ApplicationRouter};
+use crate::server;
 
 #[async_trait]
 #[clonable]
 pub trait AppHandler: std::fmt::Debug + Clone + Send + Sync {
+    /// Adjusts the claims before the JWT is issued, e.g. adds roles or
+    /// application defined claims with `JwtClaims::set_extra`.
     async fn on_login_callback(
         &self,
         claims: &mut JwtClaims,
         config: &Configuration,
         db: db::DB,
     ) -> anyhow::Result<()>;
+
+    /// Delivers the password reset token of a local user, e.g. by email.
+    /// Password resets are not possible without an implementation.
+    async fn on_password_reset_requested(
+        &self,
+        _credential: &LocalCredential,
+        _token: &str,
+        _config: &Configuration,
+    ) -> anyhow::Result<()> {
+        Err(anyhow::anyhow!("No password reset delivery implemented"))
+    }
+
+    /// Called after a failed login with the provider name (`local` for
+    /// local users) and the error code, e.g. for auditing or rate limiting.
+    async fn on_login_failure(
+        &self,
+        _provider_name: &str,
+        _error: &str,
+        _config: &Configuration,
+        _db: db::DB,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called on logout with the claims of the (possibly expired) JWT.
+    async fn on_logout(
+        &self,
+        _claims: &JwtClaims,
+        _config: &Configuration,
+        _db: db::DB,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Adjusts the claims before a refreshed JWT is issued. An error rejects
+    /// the refresh.
+    async fn on_token_refresh(
+        &self,
+        _claims: &mut JwtClaims,
+        _config: &Configuration,
+        _db: db::DB,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called for every successfully authenticated request. An error treats
+    /// the request as anonymous, e.g. for blocked users.
+    async fn on_request_authenticated(
+        &self,
+        _claims: &JwtClaims,
+        _config: &Configuration,
+        _db: db::DB,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called with the database pool before the server binds. An error
+    /// aborts the startup.
+    async fn on_startup(&self, _config: &Configuration, _db: db::DB) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called after the server stopped.
+    async fn on_shutdown(&self, _config: &Configuration) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Additional checks reported by `/readyz` as `app:<name>`, e.g. for
+    /// services the application depends on. A failed check marks the
+    /// instance not ready.
+    async fn readiness_checks(&self, _config: &Configuration, _db: db::DB) -> Vec<CheckResult> {
+        vec![]
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -46,20 +131,39 @@ impl AppHandler for AureliaAppHandler {
 pub struct ApplicationState {
     pub configuration: Arc<Configuration>,
     pub handlers: Box<dyn AppHandler>,
+    pub oidc_metadata: OidcMetadataCache,
+    pub revocations: RevocationList,
+    pub metrics: Metrics,
+    pub keys: KeySet,
 }
 
 impl ApplicationState {
-    pub fn new(cfg: Configuration, handlers: Box<dyn AppHandler>) -> ApplicationState {
-        Self {
+    pub fn new(
+        cfg: Configuration,
+        handlers: Box<dyn AppHandler>,
+    ) -> anyhow::Result<ApplicationState> {
+        let oidc_metadata = OidcMetadataCache::new(Duration::from_secs(
+            cfg.application.auth.oidc_metadata_ttl_seconds,
+        ));
+        let revocations = RevocationList::new(Duration::from_secs(
+            cfg.application.auth.revocation_reload_seconds,
+        ));
+
+        Ok(Self {
+            keys: KeySet::new(&cfg.application.auth)?,
             configuration: Arc::new(cfg),
             handlers,
-        }
+            oidc_metadata,
+            revocations,
+            metrics: Metrics::default(),
+        })
     }
 }
 
 pub struct Application {
     pub state: ApplicationState,
     router: ApplicationRouter,
+    listener: Option<TcpListener>,
 }
 
 impl Application {
@@ -76,10 +180,10 @@ impl Application {
 
         let app_state;
         if cfg.is_none() {
-            app_state = ApplicationState::new(configuration::Configuration::new(None)?, handlers);
+            app_state = ApplicationState::new(configuration::Configuration::new(None)?, handlers)?;
             tracing::info!("Parsed configuration: {:?}", app_state.configuration);
         } else {
-            app_state = ApplicationState::new(cfg.unwrap(), handlers);
+            app_state = ApplicationState::new(cfg.unwrap(), handlers)?;
             tracing::info!("Got external configuration: {:?}", app_state.configuration);
         }
 
@@ -89,6 +193,7 @@ impl Application {
         Ok(Self {
             state: app_state,
             router,
+            listener: None,
         })
     }
 
@@ -97,6 +202,21 @@ impl Application {
         self
     }
 
+    pub fn with_well_known_routes(mut self) -> Self {
+        self.router = self.router.with_well_known_routes();
+        self
+    }
+
+    pub fn with_health_routes(mut self) -> Self {
+        self.router = self.router.with_health_routes();
+        self
+    }
+
+    pub fn with_metrics(mut self) -> Self {
+        self.router = self.router.with_metrics();
+        self
+    }
+
     pub fn with_extra_routes(mut self, routes: Router) -> Self {
         self.router = self.router.with_extra_routes(routes);
         self
@@ -112,6 +232,14 @@ impl Application {
         self
     }
 
+    /// Serves the primary address on an already bound listener instead of
+    /// binding `address` and `port`, e.g. to configure a random port before
+    /// the application is initialized.
+    pub fn with_listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
     pub fn with_extension<E>(mut self, ext: Extension<E>) -> Self
     where
         E: Clone + Sync + Send + 'static,
@@ -120,31 +248,236 @@ impl Application {
         self
     }
 
-    pub async fn prepare(self) -> Result<PreparedApplication, hyper::Error> {
+    pub async fn prepare(mut self) -> anyhow::Result<PreparedApplication> {
         let db = init_connection(&self.state.configuration).await;
+        self.state
+            .handlers
+            .on_startup(&self.state.configuration, db.clone())
+            .await?;
+        self.state.revocations.spawn_refresh(db.clone());
 
-        let addr = SocketAddr::from((
-            self.state.configuration.http.address,
-            self.state.configuration.http.port,
-        ));
+        let (draining, draining_rx) = watch::channel(false);
+        self.state.oidc_metadata.spawn_refresh(
+            self.state
+                .configuration
+                .application
+                .auth
+                .oidc
+                .values()
+                .cloned()
+                .collect(),
+            draining_rx.clone(),
+        );
+
+        let http = &self.state.configuration.http;
+        let router = self.router.finalize(db.clone());
+        let tls = match &http.tls {
+            Some(tls) => Some(server::load_tls(tls, draining_rx.clone()).await?),
+            None => None,
+        };
+        let mut servers = vec![];
+        let mut local_addrs = vec![];
+
+        let primary = ListenAddress::Tcp {
+            ip: http.address,
+            port: Some(http.port),
+        };
+        let mut primary_listener = self.listener.take();
+        for listen in std::iter::once(&primary).chain(&http.listen) {
+            match listen {
+                ListenAddress::Tcp { ip, port } => {
+                    let listener = match primary_listener.take() {
+                        Some(listener) => {
+                            listener.set_nonblocking(true)?;
+                            listener
+                        }
+                        None => server::bind_tcp(SocketAddr::new(*ip, port.unwrap_or(http.port)))?,
+                    };
+                    let local_addr = listener.local_addr()?;
+                    servers.push(match &tls {
+                        Some(tls) => server::serve_https(
+                            listener,
+                            router.clone(),
+                            tls.clone(),
+                            draining_rx.clone(),
+                        )?,
+                        None => server::serve_http(listener, router.clone(), draining_rx.clone())?,
+                    });
+                    local_addrs.push(BoundAddress::Tcp(local_addr));
+                }
+                ListenAddress::Unix(path) => {
+                    servers.push(server::serve_unix(
+                        path,
+                        http.unix_socket_mode.as_deref(),
+                        router.clone(),
+                        draining_rx.clone(),
+                    )?);
+                    local_addrs.push(BoundAddress::Unix(path.clone()));
+                }
+            }
+        }
+        for addr in local_addrs.iter() {
+            tracing::info!("http server is listening on \"{}\"", addr);
+        }
 
-        let server = axum::Server::bind(&addr).serve(self.router.finalize(db).into_make_service());
-        tracing::info!("http server is listening on \"{}\"", server.local_addr());
+        // Redirects to the port of the primary address.
+        if let Some(redirect_port) = http.tls.as_ref().and_then(|tls| tls.redirect_port) {
+            let listener = server::bind_tcp(SocketAddr::new(http.address, redirect_port))?;
+            let local_addr = listener.local_addr()?;
+            tracing::info!("http redirect to https is listening on \"{}\"", local_addr);
+
+            servers.push(server::serve_http(
+                listener,
+                server::https_redirect(local_addrs[0].port().unwrap_or(http.port)),
+                draining_rx,
+            )?);
+            local_addrs.push(BoundAddress::Tcp(local_addr));
+        }
 
-        Ok(PreparedApplication { server })
+        Ok(PreparedApplication {
+            servers,
+            local_addrs,
+            state: self.state,
+            db,
+            shutdown: ShutdownHandle::default(),
+            draining,
+        })
+    }
+}
+
+/// Address a `PreparedApplication` is listening on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BoundAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl BoundAddress {
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            BoundAddress::Tcp(addr) => Some(addr.port()),
+            BoundAddress::Unix(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for BoundAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoundAddress::Tcp(addr) => write!(f, "{}", addr),
+            BoundAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Stops a running application gracefully, like SIGTERM or SIGINT do.
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownHandle {
+    notify: Arc<Notify>,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        // Stores a permit, so an early shutdown isn't lost.
+        self.notify.notify_one();
+    }
+}
+
+/// Resolves on SIGINT, SIGTERM (unix only) or a call of `ShutdownHandle::shutdown`.
+async fn shutdown_signal(handle: ShutdownHandle) {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Unable to listen for SIGINT. Error was: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Unable to listen for SIGTERM. Error was: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received SIGINT, shutting down"),
+        _ = terminate => tracing::info!("Received SIGTERM, shutting down"),
+        _ = handle.notify.notified() => tracing::info!("Shutdown requested"),
     }
 }
 
 pub struct PreparedApplication {
-    server: Server<AddrIncoming, IntoMakeService<Router>>,
+    servers: Vec<server::ServerFuture>,
+    local_addrs: Vec<BoundAddress>,
+    state: ApplicationState,
+    db: db::DB,
+    shutdown: ShutdownHandle,
+    /// Tells the servers to stop accepting connections.
+    draining: watch::Sender<bool>,
 }
 
 impl PreparedApplication {
+    /// Port of the primary `address`.
     pub fn get_port(&self) -> u16 {
-        self.server.local_addr().port()
+        self.local_addrs[0].port().unwrap_or_default()
+    }
+
+    /// All addresses the application is listening on, starting with the
+    /// primary `address`.
+    pub fn local_addrs(&self) -> &[BoundAddress] {
+        &self.local_addrs
     }
 
-    pub async fn run(self) -> Result<(), hyper::Error> {
-        self.server.await
+    /// Handle to stop the application from the outside, e.g. in tests.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Serves requests until SIGINT, SIGTERM or a `ShutdownHandle` stops the
+    /// application. No new connections are accepted then, in-flight requests
+    /// get `shutdown_timeout_seconds` to finish and the database pool is
+    /// closed.
+    pub async fn run(self) -> anyhow::Result<()> {
+        let mut servers = try_join_all(self.servers).map(|r| r.map(|_| ()));
+
+        let result = tokio::select! {
+            // Only ends early, if a server fails.
+            result = &mut servers => result,
+            _ = shutdown_signal(self.shutdown.clone()) => {
+                let _ = self.draining.send(true);
+
+                let timeout =
+                    Duration::from_secs(self.state.configuration.http.shutdown_timeout_seconds);
+                tokio::time::timeout(timeout, servers).await.unwrap_or_else(|_| {
+                    tracing::warn!(
+                        "In-flight requests didn't finish within {}s, shutting down anyway",
+                        timeout.as_secs()
+                    );
+                    Ok(())
+                })
+            }
+        };
+
+        if let Err(e) = self
+            .state
+            .handlers
+            .on_shutdown(&self.state.configuration)
+            .await
+        {
+            tracing::error!("on_shutdown handler failed. Error was: {}", e);
+        }
+
+        self.db.close().await;
+        tracing::info!("http server stopped");
+
+        result
     }
 }
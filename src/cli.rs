@@ -1,25 +1,50 @@
-use crate::{configuration::Configuration, middleware::authentication::create_jwt};
-use anyhow::Result;
-use openssl::rsa::Rsa;
+use std::time::Duration;
+
+use crate::{
+    configuration::{Configuration, JwtKey},
+    database::init_connection,
+    database_migrations::migrations,
+    driver::revocation::RevocationList,
+    keys::{generate_keypair, Jwk, KeySet},
+    middleware::authentication::{create_jwt, verify_expired_token},
+};
+use anyhow::{anyhow, Result};
 use refinery::{
     config::{Config, ConfigDbType},
     Runner,
 };
+use serde::Serialize;
 use uuid::Uuid;
 
 pub async fn cli_migrate_database<A>(cfg: &Configuration, runner: A) -> Result<()>
 where
     A: Fn() -> Runner,
 {
-    let mut conn = Config::new(ConfigDbType::Postgres)
+    migrate_aurelia_database(cfg).await?;
+    runner().run_async(&mut refinery_config(cfg)).await?;
+
+    Ok(())
+}
+
+/// Runs only Aurelia's own migrations. They are tracked in a separate history
+/// table, so they don't interfere with the versions of the application
+/// migrations.
+pub async fn migrate_aurelia_database(cfg: &Configuration) -> Result<()> {
+    migrations::runner()
+        .set_migration_table_name("aurelia_schema_history")
+        .run_async(&mut refinery_config(cfg))
+        .await?;
+
+    Ok(())
+}
+
+fn refinery_config(cfg: &Configuration) -> Config {
+    Config::new(ConfigDbType::Postgres)
         .set_db_host(&cfg.database.host)
         .set_db_port(&cfg.database.port.to_string())
         .set_db_name(&cfg.database.database_name)
         .set_db_user(&cfg.database.username)
-        .set_db_pass(&cfg.database.password);
-    runner().run_async(&mut conn).await?;
-
-    Ok(())
+        .set_db_pass(&cfg.database.password)
 }
 
 pub async fn cli_create_jwt(cfg: &Configuration, user_id: Option<Uuid>, rsa: bool) -> Result<()> {
@@ -27,12 +52,133 @@ pub async fn cli_create_jwt(cfg: &Configuration, user_id: Option<Uuid>, rsa: boo
     Ok(())
 }
 
+/// Revokes a single (possibly expired) JWT by its `jti`.
+pub async fn cli_revoke_jwt(cfg: &Configuration, token: &str) -> Result<()> {
+    let keys = KeySet::new(&cfg.application.auth)?;
+    let claims = verify_expired_token(token, cfg, &keys).await?.claims;
+    let db = init_connection(cfg).await;
+
+    revocation_list(cfg)
+        .revoke_token(&claims, &cfg.application.auth, &db)
+        .await?;
+    println!(
+        "Revoked JWT '{}' of subject '{}'.",
+        claims.jti.unwrap_or_default(),
+        claims.sub
+    );
+    Ok(())
+}
+
+/// Revokes all JWTs issued until now for the given subject.
+pub async fn cli_revoke_subject(cfg: &Configuration, subject: &str) -> Result<()> {
+    let db = init_connection(cfg).await;
+
+    revocation_list(cfg)
+        .revoke_subject(subject, &cfg.application.auth, &db)
+        .await?;
+    println!("Revoked all JWTs of subject '{}'.", subject);
+    Ok(())
+}
+
+fn revocation_list(cfg: &Configuration) -> RevocationList {
+    RevocationList::new(Duration::from_secs(
+        cfg.application.auth.revocation_reload_seconds,
+    ))
+}
+
 pub fn create_rsa_keypair() -> Result<()> {
-    let rsa = Rsa::generate(4096).unwrap();
+    create_keypair("RS256")
+}
+
+/// Prints a new key pair for the given `jwt_algorithm`, e.g. an EC key for
+/// ES256 / ES384 or an Ed25519 key for EdDSA.
+pub fn create_keypair(algorithm: &str) -> Result<()> {
+    let (private_key, public_key) = generate_keypair(algorithm.parse()?)?;
+    println!("{}\n\n\n{}", private_key, public_key);
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JwtKeysSnippet {
+    application: JwtKeysApplication,
+}
+
+#[derive(Serialize)]
+struct JwtKeysApplication {
+    auth: JwtKeysAuth,
+}
+
+#[derive(Serialize)]
+struct JwtKeysAuth {
+    jwt_keys: Vec<JwtKey>,
+}
+
+fn print_jwt_keys(jwt_keys: Vec<JwtKey>) -> Result<()> {
     println!(
-        "{}\n\n\n{}",
-        String::from_utf8(rsa.private_key_to_pem()?)?,
-        String::from_utf8(rsa.public_key_to_pem()?)?
+        "{}",
+        toml::to_string(&JwtKeysSnippet {
+            application: JwtKeysApplication {
+                auth: JwtKeysAuth { jwt_keys },
+            },
+        })?
     );
     Ok(())
 }
+
+/// Lists all keys of the JWT key set.
+pub fn cli_list_jwt_keys(cfg: &Configuration) -> Result<()> {
+    for key in KeySet::new(&cfg.application.auth)?.keys() {
+        println!("{}{}", key.kid, if key.active { " (active)" } else { "" });
+    }
+    Ok(())
+}
+
+/// Generates a new, inactive key for the configured `jwt_algorithm` and prints
+/// the configuration for it. Once it is deployed everywhere JWTs are verified,
+/// it can be activated.
+pub fn cli_add_jwt_key(cfg: &Configuration) -> Result<()> {
+    let algorithm = &cfg.application.auth.jwt_algorithm;
+    let (private_key, public_key) = generate_keypair(algorithm.parse()?)?;
+
+    let key = JwtKey {
+        kid: Some(Jwk::from_public_key_pem(&public_key, algorithm)?.kid),
+        private_key: Some(private_key),
+        public_key,
+        active: false,
+    };
+
+    let mut jwt_keys = cfg.application.auth.jwt_keys.clone();
+    jwt_keys.push(key);
+    print_jwt_keys(jwt_keys)
+}
+
+/// Prints the configuration of the JWT key set without the given key. The
+/// key is retired once all JWTs signed with it are expired.
+pub fn cli_retire_jwt_key(cfg: &Configuration, kid: &str) -> Result<()> {
+    let auth = &cfg.application.auth;
+    let keys = KeySet::new(auth)?;
+    let key = keys
+        .keys()
+        .iter()
+        .find(|k| k.kid == kid)
+        .ok_or_else(|| anyhow!("No JWT key found for kid: {}", kid))?;
+
+    if key.active {
+        return Err(anyhow!(
+            "Key {} is active. Activate another key before retiring it.",
+            kid
+        ));
+    }
+
+    if key.public_key == auth.jwt_rsa_public_key {
+        println!("Set 'jwt_rsa_private_key' and 'jwt_rsa_public_key' to \"\".");
+    }
+
+    let jwt_keys = auth
+        .jwt_keys
+        .iter()
+        .filter(|k| k.public_key != key.public_key)
+        .cloned()
+        .collect();
+    print_jwt_keys(jwt_keys)
+}
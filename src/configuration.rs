@@ -1,7 +1,12 @@
-use std::{collections::HashMap, fmt, net::Ipv4Addr};
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
 
 use config::{Config, ConfigError, Environment, File};
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Serialize};
 
 /// Database configuration
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -16,21 +21,105 @@ pub struct Database {
 /// Http configuration
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Http {
-    #[serde(deserialize_with = "ip_string_to_octets")]
-    pub address: Ipv4Addr,
+    /// IPv4 or IPv6 address, `::` usually listens on IPv4 as well.
+    pub address: IpAddr,
     pub port: u16,
+    /// Further listeners, e.g. `127.0.0.1`, `[::1]:8080` or `unix:/path`.
+    /// Addresses without a port use `port`.
+    #[serde(default)]
+    pub listen: Vec<ListenAddress>,
+    /// Octal permissions of unix sockets, e.g. `660`.
+    #[serde(default)]
+    pub unix_socket_mode: Option<String>,
     pub base_url: String,
+    /// How long in-flight requests may take to finish on shutdown.
+    #[serde(default = "default_shutdown_timeout_seconds")]
+    pub shutdown_timeout_seconds: u64,
+    /// Serves HTTPS instead of plain HTTP, if configured.
+    #[serde(default)]
+    pub tls: Option<Tls>,
+}
+
+/// Address of an additional listener
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ListenAddress {
+    Tcp { ip: IpAddr, port: Option<u16> },
+    Unix(PathBuf),
+}
+
+impl TryFrom<String> for ListenAddress {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if let Some(path) = value.strip_prefix("unix:") {
+            return Ok(ListenAddress::Unix(path.into()));
+        }
+        if let Ok(ip) = value.parse::<IpAddr>() {
+            return Ok(ListenAddress::Tcp { ip, port: None });
+        }
+        value
+            .parse::<SocketAddr>()
+            .map(|addr| ListenAddress::Tcp {
+                ip: addr.ip(),
+                port: Some(addr.port()),
+            })
+            .map_err(|_| format!("Invalid listen address: {}", value))
+    }
+}
+
+impl From<ListenAddress> for String {
+    fn from(value: ListenAddress) -> Self {
+        match value {
+            ListenAddress::Tcp { ip, port: None } => ip.to_string(),
+            ListenAddress::Tcp {
+                ip,
+                port: Some(port),
+            } => SocketAddr::new(ip, port).to_string(),
+            ListenAddress::Unix(path) => format!("unix:{}", path.display()),
+        }
+    }
+}
+
+/// TLS termination with rustls
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Tls {
+    /// PEM file with the certificate chain
+    pub cert_path: String,
+    /// PEM file with the private key
+    pub key_path: String,
+    /// Interval for checking the files for a renewed certificate.
+    #[serde(default = "default_tls_reload_seconds")]
+    pub reload_seconds: u64,
+    /// Port of an additional plain HTTP listener, which redirects to HTTPS.
+    #[serde(default)]
+    pub redirect_port: Option<u16>,
+}
+
+fn default_tls_reload_seconds() -> u64 {
+    60
+}
+
+fn default_shutdown_timeout_seconds() -> u64 {
+    30
 }
 
 impl Http {
+    /// The base url incl. a non default port. With TLS the scheme is always
+    /// `https`.
     pub fn full_base_url(&self) -> String {
-        if (self.port == 80 && self.base_url.starts_with("http://"))
-            || (self.port == 443 && self.base_url.starts_with("https://"))
+        let base_url = match (&self.tls, self.base_url.strip_prefix("http://")) {
+            (Some(_), Some(host)) => format!("https://{}", host),
+            _ => self.base_url.clone(),
+        };
+
+        if (self.port == 80 && base_url.starts_with("http://"))
+            || (self.port == 443 && base_url.starts_with("https://"))
         {
-            return self.base_url.clone();
+            return base_url;
         }
 
-        format!("{}:{}", self.base_url, self.port)
+        format!("{}:{}", base_url, self.port)
     }
 }
 
@@ -56,15 +145,112 @@ pub struct Auth {
     pub jwt_expiration_offset_seconds: usize,
     pub jwt_header_name: String,
     pub jwt_cookie_name: String,
+    /// Cookies are always `secure` with `http.tls`, see `Auth::cookie_secure`.
     pub jwt_cookie_secure: bool,
+    /// One of HS256/384/512 (signed with `jwt_secret`), RS256/384/512,
+    /// PS256/384/512, ES256/384 or EdDSA (signed with the key pair).
     pub jwt_algorithm: String,
+    /// PEM encoded private key, despite the name also EC or Ed25519 (PKCS#8).
     pub jwt_rsa_private_key: String,
+    /// PEM encoded public key matching `jwt_rsa_private_key`.
     pub jwt_rsa_public_key: String,
+    /// Additional keys for key rotation. The `active` key signs new JWTs
+    /// (instead of `jwt_rsa_private_key`), all keys verify JWTs by `kid`.
+    #[serde(default)]
+    pub jwt_keys: Vec<JwtKey>,
+    /// Expected `iss` of JWTs, defaults to the `full_base_url` of `http`,
+    /// see `Auth::issuer`.
+    #[serde(default)]
+    pub jwt_issuer: Option<String>,
+    /// Expected `aud` of JWTs, which is neither set nor validated if empty.
+    #[serde(default)]
+    pub jwt_audience: Option<String>,
+    /// Allowed clock skew for validating `exp`, `nbf` and `iat`.
+    #[serde(default = "default_jwt_leeway_seconds")]
+    pub jwt_leeway_seconds: u64,
     pub path_prefix: String,
     pub oidc: HashMap<String, Oidc>,
     pub redirect_on_login_success: String,
+    /// Where users land after a failed OIDC login, with an `error` code
+    /// appended as query parameter.
     pub redirect_on_login_error: String,
+    /// Where users land after `/auth/logout`.
+    #[serde(default = "default_redirect_on_logout")]
+    pub redirect_on_logout: String,
     pub login_path: String,
+    /// Allowed `return_to` targets after login: paths (`/app`) for relative
+    /// targets and absolute URLs (`https://app.example.com/`) for other hosts.
+    #[serde(default = "default_return_to_allowlist")]
+    pub return_to_allowlist: Vec<String>,
+    /// Name of the signed cookie holding the state of a running OIDC login.
+    #[serde(default = "default_oidc_state_cookie_name")]
+    pub oidc_state_cookie_name: String,
+    /// Lifetime of the OIDC login state, i.e. the time a user has to
+    /// finish the login at the provider.
+    #[serde(default = "default_oidc_state_expiration_seconds")]
+    pub oidc_state_expiration_seconds: usize,
+    /// Time after which cached OIDC discovery metadata (incl. JWKS) is
+    /// considered stale.
+    #[serde(default = "default_oidc_metadata_ttl_seconds")]
+    pub oidc_metadata_ttl_seconds: u64,
+    /// Maximum lifetime of a stored provider refresh token, after which the
+    /// user has to log in at the provider again.
+    #[serde(default = "default_refresh_token_expiration_seconds")]
+    pub refresh_token_expiration_seconds: usize,
+    /// Interval for reloading revoked JWTs from the database, i.e. the time
+    /// until revocations of other instances become effective.
+    #[serde(default = "default_revocation_reload_seconds")]
+    pub revocation_reload_seconds: u64,
+    /// HTTP header carrying an API key. Alternatively API keys are accepted as
+    /// Bearer token in `jwt_header_name`, recognized by `api_key_prefix`.
+    #[serde(default = "default_api_key_header_name")]
+    pub api_key_header_name: String,
+    /// Start of every generated API key, e.g. `ak_...`.
+    #[serde(default = "default_api_key_prefix")]
+    pub api_key_prefix: String,
+    /// Local email/password authentication, disabled by default.
+    #[serde(default)]
+    pub local: LocalAuth,
+}
+
+fn default_redirect_on_logout() -> String {
+    "/".into()
+}
+
+fn default_jwt_leeway_seconds() -> u64 {
+    60
+}
+
+fn default_return_to_allowlist() -> Vec<String> {
+    vec!["/".into()]
+}
+
+fn default_oidc_state_cookie_name() -> String {
+    "oidc_state".into()
+}
+
+fn default_oidc_state_expiration_seconds() -> usize {
+    600
+}
+
+fn default_oidc_metadata_ttl_seconds() -> u64 {
+    3600
+}
+
+fn default_refresh_token_expiration_seconds() -> usize {
+    2592000
+}
+
+fn default_revocation_reload_seconds() -> u64 {
+    30
+}
+
+fn default_api_key_header_name() -> String {
+    "x-api-key".into()
+}
+
+fn default_api_key_prefix() -> String {
+    "ak".into()
 }
 
 impl std::fmt::Debug for Auth {
@@ -81,26 +267,125 @@ impl std::fmt::Debug for Auth {
             .field("jwt_algorithm", &self.jwt_algorithm)
             .field("jwt_rsa_private_key", &"***")
             .field("jwt_rsa_public_key", &"***")
+            .field("jwt_keys", &self.jwt_keys)
+            .field("jwt_issuer", &self.jwt_issuer)
+            .field("jwt_audience", &self.jwt_audience)
+            .field("jwt_leeway_seconds", &self.jwt_leeway_seconds)
             .field("path_prefix", &self.path_prefix)
             .field("oidc", &self.oidc)
             .field("redirect_on_login_success", &self.redirect_on_login_success)
             .field("redirect_on_login_error", &self.redirect_on_login_error)
+            .field("redirect_on_logout", &self.redirect_on_logout)
             .field("login_path", &self.login_path)
+            .field("return_to_allowlist", &self.return_to_allowlist)
+            .field("oidc_state_cookie_name", &self.oidc_state_cookie_name)
+            .field(
+                "oidc_state_expiration_seconds",
+                &self.oidc_state_expiration_seconds,
+            )
+            .field("oidc_metadata_ttl_seconds", &self.oidc_metadata_ttl_seconds)
+            .field(
+                "refresh_token_expiration_seconds",
+                &self.refresh_token_expiration_seconds,
+            )
+            .field("revocation_reload_seconds", &self.revocation_reload_seconds)
+            .field("api_key_header_name", &self.api_key_header_name)
+            .field("api_key_prefix", &self.api_key_prefix)
+            .field("local", &self.local)
             .finish()
     }
 }
 
+/// Local email/password authentication for deployments without an IdP
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LocalAuth {
+    pub enabled: bool,
+    /// Allow users to register themselves.
+    pub allow_registration: bool,
+    /// `client_role` claim of locally authenticated users.
+    pub client_role: String,
+    pub min_password_length: usize,
+    pub password_reset_expiration_seconds: usize,
+}
+
+impl Default for LocalAuth {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allow_registration: true,
+            client_role: "user".into(),
+            min_password_length: 8,
+            password_reset_expiration_seconds: 3600,
+        }
+    }
+}
+
+/// Key of the JWT key set
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JwtKey {
+    /// Defaults to the RFC 7638 thumbprint of the public key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    /// Only needed for the active key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_key: Option<String>,
+    pub public_key: String,
+    #[serde(default)]
+    pub active: bool,
+}
+
+impl std::fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtKey")
+            .field("kid", &self.kid)
+            .field("private_key", &"***")
+            .field("public_key", &self.public_key)
+            .field("active", &self.active)
+            .finish()
+    }
+}
+
+impl Auth {
+    /// Whether cookies are `secure`. Cookies of an HTTPS server don't need
+    /// to be sent over plain HTTP.
+    pub fn cookie_secure(&self, http: &Http) -> bool {
+        self.jwt_cookie_secure || http.tls.is_some()
+    }
+
+    /// `iss` of issued JWTs, which is also published by the discovery
+    /// document. Resolved on use, so it follows later changes of `http`.
+    pub fn issuer(&self, http: &Http) -> String {
+        self.jwt_issuer
+            .clone()
+            .unwrap_or_else(|| http.full_base_url())
+    }
+}
+
 // OpenID client configuration
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Oidc {
     pub provider_name: String,
     pub client_name: String,
     pub client_id: String,
-    pub client_secret: String,
+    /// Public clients (e.g. in combination with PKCE) don't have a secret.
+    #[serde(default)]
+    pub client_secret: Option<String>,
     pub client_scopes: Vec<String>,
     pub client_role: String,
     pub issuer_url: String,
     pub redirect_url: String,
+    /// Use PKCE (S256 code challenge) for the authorization code flow.
+    #[serde(default)]
+    pub pkce: bool,
+    /// Extra authorization request parameters, e.g. `prompt`, `audience`
+    /// or `acr_values`.
+    #[serde(default)]
+    pub authorization_params: HashMap<String, String>,
+    /// If set, logout is also initiated at the provider, which afterwards
+    /// redirects to this (registered, absolute) URL.
+    #[serde(default)]
+    pub post_logout_redirect_url: Option<String>,
 }
 
 impl std::fmt::Debug for Oidc {
@@ -114,6 +399,9 @@ impl std::fmt::Debug for Oidc {
             .field("client_role", &self.client_role)
             .field("issuer_url", &self.issuer_url)
             .field("redirect_url", &self.redirect_url)
+            .field("pkce", &self.pkce)
+            .field("authorization_params", &self.authorization_params)
+            .field("post_logout_redirect_url", &self.post_logout_redirect_url)
             .finish()
     }
 }
@@ -126,13 +414,6 @@ pub struct Configuration {
     pub application: Application,
 }
 
-fn ip_string_to_octets<'de, D>(deserializer: D) -> Result<Ipv4Addr, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    Ipv4Addr::deserialize(deserializer)
-}
-
 impl Configuration {
     /// 1. File ``etc/aurelia.toml`` (optional),
     /// 2. Env with prefix ``AURELIA_``.
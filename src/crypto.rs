@@ -0,0 +1,86 @@
+use anyhow::{anyhow, Result};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use openssl::{
+    rand::rand_bytes,
+    sha::sha256,
+    symm::{decrypt_aead, encrypt_aead, Cipher},
+};
+
+const IV_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Derives a 256 bit key for a given purpose from a configured secret.
+pub(crate) fn derive_key(secret: &str, purpose: &str) -> [u8; 32] {
+    sha256(format!("aurelia:{}:{}", purpose, secret).as_bytes())
+}
+
+/// Encrypts ``data`` with AES-256-GCM. The output is ``iv || tag || ciphertext``.
+pub fn encrypt(secret: &str, purpose: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let key = derive_key(secret, purpose);
+    let mut iv = [0u8; IV_LEN];
+    rand_bytes(&mut iv)?;
+
+    let mut tag = [0u8; TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&iv),
+        purpose.as_bytes(),
+        data,
+        &mut tag,
+    )?;
+
+    Ok([&iv[..], &tag[..], &ciphertext[..]].concat())
+}
+
+/// Decrypts data created with [`encrypt`] for the same secret and purpose.
+pub fn decrypt(secret: &str, purpose: &str, data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < IV_LEN + TAG_LEN {
+        return Err(anyhow!("Encrypted data is too short."));
+    }
+
+    let key = derive_key(secret, purpose);
+    let (iv, rest) = data.split_at(IV_LEN);
+    let (tag, ciphertext) = rest.split_at(TAG_LEN);
+
+    Ok(decrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(iv),
+        purpose.as_bytes(),
+        ciphertext,
+        tag,
+    )?)
+}
+
+/// Returns 32 random bytes as base64url string, e.g. for API keys or
+/// password reset tokens.
+pub fn random_token() -> Result<String> {
+    let mut random = [0u8; 32];
+    rand_bytes(&mut random)?;
+    Ok(base64::encode_config(random, base64::URL_SAFE_NO_PAD))
+}
+
+/// Hashes a password with Argon2id and returns it in PHC string format.
+pub fn hash_password(password: &str) -> Result<String> {
+    let mut salt = [0u8; 16];
+    rand_bytes(&mut salt)?;
+    let salt = SaltString::b64_encode(&salt)?;
+
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// Verifies a password against a PHC string created by [`hash_password`].
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
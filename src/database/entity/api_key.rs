@@ -0,0 +1,124 @@
+use chrono::{DateTime, Utc};
+use openssl::sha::sha256;
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::{crypto, driver::db};
+
+/// API key of a machine client, acting on behalf of `subject`. Only the
+/// hash of the key is stored.
+#[derive(Debug, FromRow, Serialize, Clone)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub subject: String,
+    pub name: String,
+    pub prefix: String,
+    #[serde(skip)]
+    pub key_hash: Vec<u8>,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    /// Generates a new key `{prefix}_{random}` and returns it together with
+    /// the (not yet stored) entity. The key itself is never stored.
+    pub fn generate(
+        key_prefix: &str,
+        subject: &str,
+        name: &str,
+        scopes: Vec<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<(String, Self)> {
+        let key = format!("{}_{}", key_prefix, crypto::random_token()?);
+
+        let api_key = Self {
+            id: Uuid::new_v4(),
+            subject: subject.to_string(),
+            name: name.to_string(),
+            prefix: key[..key_prefix.len() + 7].to_string(),
+            key_hash: Self::hash(&key),
+            scopes,
+            created_at: Utc::now(),
+            expires_at,
+            last_used_at: None,
+            revoked_at: None,
+        };
+
+        Ok((key, api_key))
+    }
+
+    pub fn hash(key: &str) -> Vec<u8> {
+        sha256(key.as_bytes()).to_vec()
+    }
+
+    pub async fn insert(&self, pool: &db::DB) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"INSERT INTO api_key
+            (id, subject, name, prefix, key_hash, scopes, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+        )
+        .bind(self.id)
+        .bind(&self.subject)
+        .bind(&self.name)
+        .bind(&self.prefix)
+        .bind(&self.key_hash)
+        .bind(&self.scopes)
+        .bind(self.created_at)
+        .bind(self.expires_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the key with the given hash, if it is neither revoked nor expired.
+    pub async fn get_valid_by_hash(key_hash: &[u8], pool: &db::DB) -> anyhow::Result<Option<Self>> {
+        Ok(sqlx::query_as(
+            r#"SELECT * FROM api_key WHERE key_hash = $1 AND revoked_at IS NULL
+            AND (expires_at IS NULL OR expires_at > now())"#,
+        )
+        .bind(key_hash)
+        .fetch_optional(pool)
+        .await?)
+    }
+
+    pub async fn list_for_subject(subject: &str, pool: &db::DB) -> anyhow::Result<Vec<Self>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM api_key WHERE subject = $1 ORDER BY created_at")
+                .bind(subject)
+                .fetch_all(pool)
+                .await?,
+        )
+    }
+
+    /// Revokes a key of the given subject and returns whether it existed.
+    pub async fn revoke(id: Uuid, subject: &str, pool: &db::DB) -> anyhow::Result<bool> {
+        let result = sqlx::query(
+            r#"UPDATE api_key SET revoked_at = now()
+            WHERE id = $1 AND subject = $2 AND revoked_at IS NULL"#,
+        )
+        .bind(id)
+        .bind(subject)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Tracks the usage of a key, at most once a minute.
+    pub async fn touch(id: Uuid, pool: &db::DB) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"UPDATE api_key SET last_used_at = now() WHERE id = $1
+            AND (last_used_at IS NULL OR last_used_at < now() - interval '1 minute')"#,
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
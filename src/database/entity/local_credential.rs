@@ -0,0 +1,70 @@
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::driver::db;
+
+/// Email and password of a local user. The `user_id` is the `sub` of the
+/// issued JWTs.
+#[derive(Debug, FromRow, Clone)]
+pub struct LocalCredential {
+    pub user_id: Uuid,
+    pub email: String,
+    /// Argon2id hash in PHC string format.
+    pub password_hash: String,
+}
+
+impl LocalCredential {
+    pub async fn insert(&self, pool: &db::DB) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO local_credential (user_id, email, password_hash) VALUES ($1, $2, $3)",
+        )
+        .bind(self.user_id)
+        .bind(&self.email)
+        .bind(&self.password_hash)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get(user_id: Uuid, pool: &db::DB) -> anyhow::Result<Option<Self>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM local_credential WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_optional(pool)
+                .await?,
+        )
+    }
+
+    pub async fn get_by_email(email: &str, pool: &db::DB) -> anyhow::Result<Option<Self>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM local_credential WHERE email = $1")
+                .bind(email)
+                .fetch_optional(pool)
+                .await?,
+        )
+    }
+
+    pub async fn update_password(
+        user_id: Uuid,
+        password_hash: &str,
+        pool: &db::DB,
+    ) -> anyhow::Result<()> {
+        sqlx::query("UPDATE local_credential SET password_hash = $2 WHERE user_id = $1")
+            .bind(user_id)
+            .bind(password_hash)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(user_id: Uuid, pool: &db::DB) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM local_credential WHERE user_id = $1")
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}
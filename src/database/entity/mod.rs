@@ -1,7 +1,13 @@
 // pub mod ingredient;
 // pub mod recipe;
 // pub mod tag;
-// pub mod user;
+pub mod api_key;
+pub mod local_credential;
+pub mod oidc_session;
+pub mod password_reset_token;
+pub mod token_revocation;
+pub mod user;
+pub mod user_identity;
 
 pub struct DbFilter {
     _limit: Option<usize>,
@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::driver::db;
+
+/// Session of an OIDC login. The `id` is carried as `sid` in the issued JWT.
+/// The provider refresh token is stored encrypted.
+#[derive(Debug, FromRow, Clone)]
+pub struct OidcSession {
+    pub id: Uuid,
+    pub provider_name: String,
+    pub subject: String,
+    pub refresh_token: Option<Vec<u8>>,
+    pub id_token: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl OidcSession {
+    pub async fn insert(&self, pool: &db::DB) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"INSERT INTO oidc_session
+            (id, provider_name, subject, refresh_token, id_token, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+        )
+        .bind(self.id)
+        .bind(&self.provider_name)
+        .bind(&self.subject)
+        .bind(&self.refresh_token)
+        .bind(&self.id_token)
+        .bind(self.created_at)
+        .bind(self.expires_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get(id: Uuid, pool: &db::DB) -> anyhow::Result<Option<Self>> {
+        Ok(sqlx::query_as("SELECT * FROM oidc_session WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?)
+    }
+
+    /// Returns the session, if it exists and is not expired.
+    pub async fn get_valid(id: Uuid, pool: &db::DB) -> anyhow::Result<Option<Self>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM oidc_session WHERE id = $1 AND expires_at > now()")
+                .bind(id)
+                .fetch_optional(pool)
+                .await?,
+        )
+    }
+
+    pub async fn update_refresh_token(
+        id: Uuid,
+        refresh_token: Vec<u8>,
+        pool: &db::DB,
+    ) -> anyhow::Result<()> {
+        sqlx::query("UPDATE oidc_session SET refresh_token = $2 WHERE id = $1")
+            .bind(id)
+            .bind(refresh_token)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(id: Uuid, pool: &db::DB) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM oidc_session WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_expired(pool: &db::DB) -> anyhow::Result<u64> {
+        Ok(
+            sqlx::query("DELETE FROM oidc_session WHERE expires_at <= now()")
+                .execute(pool)
+                .await?
+                .rows_affected(),
+        )
+    }
+
+    /// Deletes the sessions of all provider identities of a user.
+    pub async fn delete_for_user(user_id: Uuid, pool: &db::DB) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"DELETE FROM oidc_session s USING user_identity i
+            WHERE i.user_id = $1 AND s.provider_name = i.provider_name AND s.subject = i.subject"#,
+        )
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use chrono::{DateTime, Utc};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::driver::db;
+
+/// Single use token for resetting the password of a local user. Only the
+/// hash of the token is stored.
+#[derive(Debug, FromRow, Clone)]
+pub struct PasswordResetToken {
+    pub token_hash: Vec<u8>,
+    pub user_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+impl PasswordResetToken {
+    pub async fn insert(&self, pool: &db::DB) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"INSERT INTO password_reset_token (token_hash, user_id, created_at, expires_at)
+            VALUES ($1, $2, $3, $4)"#,
+        )
+        .bind(&self.token_hash)
+        .bind(self.user_id)
+        .bind(self.created_at)
+        .bind(self.expires_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Marks a valid token as used and returns it. Fails (returns `None`) for
+    /// unknown, expired or already used tokens.
+    pub async fn consume(token_hash: &[u8], pool: &db::DB) -> anyhow::Result<Option<Self>> {
+        Ok(sqlx::query_as(
+            r#"UPDATE password_reset_token SET used_at = now()
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+            RETURNING *"#,
+        )
+        .bind(token_hash)
+        .fetch_optional(pool)
+        .await?)
+    }
+}
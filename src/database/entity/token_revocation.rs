@@ -0,0 +1,88 @@
+use chrono::{DateTime, Utc};
+use sqlx::FromRow;
+
+use crate::driver::db;
+
+/// Revoked JWT, identified by its `jti`.
+#[derive(Debug, FromRow, Clone)]
+pub struct RevokedToken {
+    pub jti: String,
+    pub subject: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Revocation of all JWTs of a subject issued before `revoked_before`.
+#[derive(Debug, FromRow, Clone)]
+pub struct RevokedSubject {
+    pub subject: String,
+    pub revoked_before: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl RevokedToken {
+    pub async fn insert(&self, pool: &db::DB) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"INSERT INTO revoked_token (jti, subject, expires_at) VALUES ($1, $2, $3)
+            ON CONFLICT (jti) DO NOTHING"#,
+        )
+        .bind(&self.jti)
+        .bind(&self.subject)
+        .bind(self.expires_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn list_valid(pool: &db::DB) -> anyhow::Result<Vec<Self>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM revoked_token WHERE expires_at > now()")
+                .fetch_all(pool)
+                .await?,
+        )
+    }
+
+    pub async fn delete_expired(pool: &db::DB) -> anyhow::Result<u64> {
+        Ok(
+            sqlx::query("DELETE FROM revoked_token WHERE expires_at <= now()")
+                .execute(pool)
+                .await?
+                .rows_affected(),
+        )
+    }
+}
+
+impl RevokedSubject {
+    pub async fn upsert(&self, pool: &db::DB) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"INSERT INTO revoked_subject (subject, revoked_before, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (subject) DO UPDATE
+            SET revoked_before = EXCLUDED.revoked_before, expires_at = EXCLUDED.expires_at"#,
+        )
+        .bind(&self.subject)
+        .bind(self.revoked_before)
+        .bind(self.expires_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn list_valid(pool: &db::DB) -> anyhow::Result<Vec<Self>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM revoked_subject WHERE expires_at > now()")
+                .fetch_all(pool)
+                .await?,
+        )
+    }
+
+    pub async fn delete_expired(pool: &db::DB) -> anyhow::Result<u64> {
+        Ok(
+            sqlx::query("DELETE FROM revoked_subject WHERE expires_at <= now()")
+                .execute(pool)
+                .await?
+                .rows_affected(),
+        )
+    }
+}
@@ -14,7 +14,7 @@ pub struct User {
 pub async fn get_user_by_id(id: Uuid, pool: db::DB) -> Option<User> {
     sqlx::query_as(r#"SELECT * FROM "user" where id = $1"#)
         .bind(id)
-        .fetch_optional(&*pool)
+        .fetch_optional(&pool)
         .await
         .unwrap()
 }
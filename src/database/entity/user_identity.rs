@@ -0,0 +1,107 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::driver::db;
+
+/// Identity of an OIDC provider, which belongs to the internal user `user_id`.
+#[derive(Debug, FromRow, Serialize, Clone)]
+pub struct UserIdentity {
+    pub provider_name: String,
+    pub subject: String,
+    pub user_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+impl UserIdentity {
+    pub fn new(provider_name: &str, subject: &str, user_id: Uuid) -> Self {
+        Self {
+            provider_name: provider_name.to_string(),
+            subject: subject.to_string(),
+            user_id,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// Stores the identity and returns whether it was new, i.e. not already
+    /// linked to any user.
+    pub async fn insert<'c, Q: db::Queryer<'c>>(&self, queryer: Q) -> anyhow::Result<bool> {
+        let result = sqlx::query(
+            r#"INSERT INTO user_identity (provider_name, subject, user_id, created_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (provider_name, subject) DO NOTHING"#,
+        )
+        .bind(&self.provider_name)
+        .bind(&self.subject)
+        .bind(self.user_id)
+        .bind(self.created_at)
+        .execute(queryer)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn get<'c, Q: db::Queryer<'c>>(
+        provider_name: &str,
+        subject: &str,
+        queryer: Q,
+    ) -> anyhow::Result<Option<Self>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM user_identity WHERE provider_name = $1 AND subject = $2")
+                .bind(provider_name)
+                .bind(subject)
+                .fetch_optional(queryer)
+                .await?,
+        )
+    }
+
+    /// Returns the identity, or links it to a new user, if it is unknown.
+    pub async fn get_or_create(
+        provider_name: &str,
+        subject: &str,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> anyhow::Result<Self> {
+        if let Some(identity) = Self::get(provider_name, subject, &mut *tx).await? {
+            return Ok(identity);
+        }
+
+        let identity = Self::new(provider_name, subject, Uuid::new_v4());
+        if identity.insert(&mut *tx).await? {
+            return Ok(identity);
+        }
+
+        // Created concurrently, e.g. by a parallel login.
+        Self::get(provider_name, subject, &mut *tx)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("User identity vanished after conflicting insert"))
+    }
+
+    /// Links the identity to `user_id`. Returns `false`, if it is already
+    /// linked to another user.
+    pub async fn link(
+        provider_name: &str,
+        subject: &str,
+        user_id: Uuid,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> anyhow::Result<bool> {
+        let identity = Self::new(provider_name, subject, user_id);
+        if identity.insert(&mut *tx).await? {
+            return Ok(true);
+        }
+
+        Ok(matches!(
+            Self::get(provider_name, subject, &mut *tx).await?,
+            Some(existing) if existing.user_id == user_id
+        ))
+    }
+
+    pub async fn list_for_user(user_id: Uuid, pool: &db::DB) -> anyhow::Result<Vec<Self>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM user_identity WHERE user_id = $1 ORDER BY created_at")
+                .bind(user_id)
+                .fetch_all(pool)
+                .await?,
+        )
+    }
+}
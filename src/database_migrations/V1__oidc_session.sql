@@ -0,0 +1,13 @@
+-- Every OIDC login is stored as a session, which optionally holds a refresh
+-- token and the provider id token (used as `id_token_hint` on logout).
+CREATE TABLE oidc_session (
+    id UUID PRIMARY KEY,
+    provider_name TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    refresh_token BYTEA,
+    id_token TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX oidc_session_expires_at_idx ON oidc_session (expires_at);
@@ -0,0 +1,16 @@
+-- API keys for machine clients. Only the SHA-256 hash of a key is stored,
+-- the `prefix` (the start of the key) helps users to recognize their keys.
+CREATE TABLE api_key (
+    id UUID PRIMARY KEY,
+    subject TEXT NOT NULL,
+    name TEXT NOT NULL,
+    prefix TEXT NOT NULL,
+    key_hash BYTEA NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX api_key_subject_idx ON api_key (subject);
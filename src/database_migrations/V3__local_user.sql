@@ -0,0 +1,19 @@
+-- Credentials of the local email/password authentication. The `user_id` is
+-- the `sub` of the issued JWTs, the users themselves belong to the
+-- application.
+CREATE TABLE local_credential (
+    user_id UUID PRIMARY KEY,
+    email TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL
+);
+
+-- Only the SHA-256 hash of a reset token is stored.
+CREATE TABLE password_reset_token (
+    token_hash BYTEA PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES local_credential (user_id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ
+);
+
+CREATE INDEX password_reset_token_user_id_idx ON password_reset_token (user_id);
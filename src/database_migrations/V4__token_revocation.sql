@@ -0,0 +1,18 @@
+-- Revoked JWTs by `jti` and revocations of all JWTs of a subject, which were
+-- issued before `revoked_before`. Entries are deleted once all affected JWTs
+-- are expired.
+CREATE TABLE revoked_token (
+    jti TEXT PRIMARY KEY,
+    subject TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX revoked_token_expires_at_idx ON revoked_token (expires_at);
+
+CREATE TABLE revoked_subject (
+    subject TEXT PRIMARY KEY,
+    revoked_before TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX revoked_subject_expires_at_idx ON revoked_subject (expires_at);
@@ -0,0 +1,12 @@
+-- Maps the identity of an OIDC provider (provider, subject) to the internal
+-- user id, which is the `sub` of the issued JWTs. A user may have identities
+-- of several providers.
+CREATE TABLE user_identity (
+    provider_name TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    user_id UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (provider_name, subject)
+);
+
+CREATE INDEX user_identity_user_id_idx ON user_identity (user_id);
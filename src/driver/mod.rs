@@ -1 +1,3 @@
 pub mod db;
+pub mod oidc;
+pub mod revocation;
@@ -0,0 +1,182 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use openidconnect::{
+    core::{
+        CoreAuthDisplay, CoreClaimName, CoreClaimType, CoreClientAuthMethod, CoreGrantType,
+        CoreJsonWebKey, CoreJsonWebKeyType, CoreJsonWebKeyUse, CoreJweContentEncryptionAlgorithm,
+        CoreJweKeyManagementAlgorithm, CoreJwsSigningAlgorithm, CoreResponseMode, CoreResponseType,
+        CoreSubjectIdentifierType,
+    },
+    reqwest::async_http_client,
+    AdditionalProviderMetadata, IssuerUrl, ProviderMetadata,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{watch, RwLock};
+
+use crate::{configuration::Oidc, server::shutdown_started};
+
+/// Longest wait for the discovery of a provider, so an unreachable issuer
+/// doesn't block logins until the TCP connection times out.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Discovery metadata beyond OpenID Connect Discovery 1.0, which is used by Aurelia.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AureliaAdditionalProviderMetadata {
+    /// RP-Initiated Logout 1.0
+    pub end_session_endpoint: Option<String>,
+}
+impl AdditionalProviderMetadata for AureliaAdditionalProviderMetadata {}
+
+pub type AureliaProviderMetadata = ProviderMetadata<
+    AureliaAdditionalProviderMetadata,
+    CoreAuthDisplay,
+    CoreClientAuthMethod,
+    CoreClaimName,
+    CoreClaimType,
+    CoreGrantType,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJweKeyManagementAlgorithm,
+    CoreJwsSigningAlgorithm,
+    CoreJsonWebKeyType,
+    CoreJsonWebKeyUse,
+    CoreJsonWebKey,
+    CoreResponseMode,
+    CoreResponseType,
+    CoreSubjectIdentifierType,
+>;
+
+#[derive(Debug, Clone)]
+struct CachedMetadata {
+    metadata: AureliaProviderMetadata,
+    fetched_at: Instant,
+}
+
+/// Per provider cache of the OpenID Connect discovery document including
+/// the provider's JWKS. Entries are refreshed in the background and the last
+/// successfully discovered metadata is served while the issuer is unreachable.
+#[derive(Debug, Clone)]
+pub struct OidcMetadataCache {
+    entries: Arc<RwLock<HashMap<String, CachedMetadata>>>,
+    /// Providers with a refresh of a stale entry in flight.
+    refreshing: Arc<Mutex<HashSet<String>>>,
+    ttl: Duration,
+}
+
+impl OidcMetadataCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: Arc::new(RwLock::new(HashMap::new())),
+            refreshing: Arc::new(Mutex::new(HashSet::new())),
+            ttl,
+        }
+    }
+
+    /// Returns the provider metadata, discovering it if there is no cache
+    /// entry yet. Stale entries are served right away and refreshed in the
+    /// background.
+    pub async fn get(&self, provider: &Oidc) -> Result<AureliaProviderMetadata> {
+        let cached = self
+            .entries
+            .read()
+            .await
+            .get(&provider.provider_name)
+            .cloned();
+
+        match cached {
+            Some(c) if c.fetched_at.elapsed() < self.ttl => Ok(c.metadata),
+            Some(c) => {
+                self.spawn_stale_refresh(provider);
+                Ok(c.metadata)
+            }
+            None => self.refresh(provider).await,
+        }
+    }
+
+    /// Refreshes a stale entry, unless a refresh of the provider is running
+    /// already.
+    fn spawn_stale_refresh(&self, provider: &Oidc) {
+        if !self
+            .refreshing
+            .lock()
+            .unwrap()
+            .insert(provider.provider_name.clone())
+        {
+            return;
+        }
+
+        let (cache, provider) = (self.clone(), provider.clone());
+        tokio::spawn(async move {
+            if let Err(e) = cache.refresh(&provider).await {
+                tracing::warn!(
+                    "Discovery for provider '{}' failed, serving stale metadata. Error was: {}",
+                    provider.provider_name,
+                    e
+                );
+            }
+            cache
+                .refreshing
+                .lock()
+                .unwrap()
+                .remove(&provider.provider_name);
+        });
+    }
+
+    /// Discovers the provider metadata and replaces the cache entry on success.
+    pub async fn refresh(&self, provider: &Oidc) -> Result<AureliaProviderMetadata> {
+        let discovery = AureliaProviderMetadata::discover_async(
+            IssuerUrl::new(provider.issuer_url.clone())?,
+            async_http_client,
+        );
+        let metadata = tokio::time::timeout(DISCOVERY_TIMEOUT, discovery)
+            .await
+            .map_err(|_| anyhow!("Discovery timed out after {:?}", DISCOVERY_TIMEOUT))?
+            .map_err(|e| anyhow!("Discovery Error: {}", e))?;
+
+        self.entries.write().await.insert(
+            provider.provider_name.clone(),
+            CachedMetadata {
+                metadata: metadata.clone(),
+                fetched_at: Instant::now(),
+            },
+        );
+
+        Ok(metadata)
+    }
+
+    /// Spawns a task, which warms the cache and refreshes all given providers
+    /// every half TTL, so requests usually never wait for the issuer. The task
+    /// ends with the shutdown of the application.
+    pub fn spawn_refresh(&self, providers: Vec<Oidc>, shutdown: watch::Receiver<bool>) {
+        if providers.is_empty() {
+            return;
+        }
+
+        let cache = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval((cache.ttl / 2).max(Duration::from_secs(1)));
+            let stopped = shutdown_started(shutdown);
+            tokio::pin!(stopped);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = &mut stopped => break,
+                }
+                for provider in providers.iter() {
+                    if let Err(e) = cache.refresh(provider).await {
+                        tracing::warn!(
+                            "Background discovery for provider '{}' failed. Error was: {}",
+                            provider.provider_name,
+                            e
+                        );
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use tokio::sync::RwLock;
+
+use crate::{
+    configuration::Auth,
+    database::entity::{
+        oidc_session::OidcSession,
+        token_revocation::{RevokedSubject, RevokedToken},
+    },
+    driver::db,
+    middleware::authentication::JwtClaims,
+};
+
+#[derive(Debug, Default)]
+struct Revocations {
+    jtis: HashSet<String>,
+    subjects: HashMap<String, DateTime<Utc>>,
+}
+
+/// In-memory copy of the revoked JWTs stored in Postgres, so checking a JWT
+/// never hits the database. Revocations of other instances become effective
+/// after the next reload.
+#[derive(Debug, Clone)]
+pub struct RevocationList {
+    revocations: Arc<RwLock<Revocations>>,
+    reload_interval: Duration,
+}
+
+impl RevocationList {
+    pub fn new(reload_interval: Duration) -> Self {
+        Self {
+            revocations: Arc::new(RwLock::new(Revocations::default())),
+            reload_interval,
+        }
+    }
+
+    /// Whether the JWT is revoked by its `jti` or is issued before all JWTs
+    /// of its subject were revoked. JWTs without `iat` count as old.
+    /// As `iat` has a resolution of seconds, JWTs issued within the second of
+    /// the revocation are kept, e.g. of a fresh login right after "log out
+    /// everywhere".
+    pub async fn is_revoked(&self, claims: &JwtClaims) -> bool {
+        let revocations = self.revocations.read().await;
+
+        if matches!(&claims.jti, Some(jti) if revocations.jtis.contains(jti)) {
+            return true;
+        }
+
+        match revocations.subjects.get(&claims.sub) {
+            Some(revoked_before) => match claims.iat {
+                Some(iat) => (iat as i64) < revoked_before.timestamp(),
+                None => true,
+            },
+            None => false,
+        }
+    }
+
+    /// Revokes a single JWT. It stays revoked as long as it could be used,
+    /// i.e. also for refreshing it, if it belongs to an OIDC session.
+    pub async fn revoke_token(&self, claims: &JwtClaims, auth: &Auth, db: &db::DB) -> Result<()> {
+        let jti = claims
+            .jti
+            .clone()
+            .ok_or_else(|| anyhow!("JWT without 'jti' can't be revoked individually."))?;
+
+        let refreshable_for = match claims.sid {
+            Some(_) => auth.refresh_token_expiration_seconds,
+            None => 0,
+        };
+        let expires_at = Utc
+            .timestamp_opt((claims.exp + refreshable_for) as i64, 0)
+            .single()
+            .ok_or_else(|| anyhow!("Invalid 'exp' of JWT: {}", claims.exp))?;
+
+        RevokedToken {
+            jti: jti.clone(),
+            subject: claims.sub.clone(),
+            expires_at,
+        }
+        .insert(db)
+        .await?;
+        self.revocations.write().await.jtis.insert(jti);
+
+        Ok(())
+    }
+
+    /// Revokes all JWTs of a subject, which are issued before the current
+    /// second, see `is_revoked`.
+    pub async fn revoke_subject(&self, subject: &str, auth: &Auth, db: &db::DB) -> Result<()> {
+        let now = Utc::now();
+        let lifetime = auth
+            .jwt_expiration_offset_seconds
+            .max(auth.refresh_token_expiration_seconds);
+
+        RevokedSubject {
+            subject: subject.to_string(),
+            revoked_before: now,
+            expires_at: now + chrono::Duration::seconds(lifetime as i64),
+        }
+        .upsert(db)
+        .await?;
+        self.revocations
+            .write()
+            .await
+            .subjects
+            .insert(subject.to_string(), now);
+
+        Ok(())
+    }
+
+    /// Replaces the in-memory copy with the revocations from the database.
+    pub async fn reload(&self, db: &db::DB) -> Result<()> {
+        let jtis = RevokedToken::list_valid(db)
+            .await?
+            .into_iter()
+            .map(|t| t.jti)
+            .collect();
+        let subjects = RevokedSubject::list_valid(db)
+            .await?
+            .into_iter()
+            .map(|s| (s.subject, s.revoked_before))
+            .collect();
+
+        *self.revocations.write().await = Revocations { jtis, subjects };
+
+        Ok(())
+    }
+
+    /// Spawns a task, which periodically deletes expired revocations and OIDC
+    /// sessions and reloads the in-memory copy.
+    pub fn spawn_refresh(&self, db: db::DB) {
+        let list = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(list.reload_interval);
+            loop {
+                interval.tick().await;
+                // The pool is closed on shutdown.
+                if db.is_closed() {
+                    break;
+                }
+                if let Err(e) = RevokedToken::delete_expired(&db).await {
+                    tracing::warn!("Unable to delete expired revoked tokens. Error was: {}", e);
+                }
+                if let Err(e) = RevokedSubject::delete_expired(&db).await {
+                    tracing::warn!(
+                        "Unable to delete expired revoked subjects. Error was: {}",
+                        e
+                    );
+                }
+                if let Err(e) = OidcSession::delete_expired(&db).await {
+                    tracing::warn!("Unable to delete expired OIDC sessions. Error was: {}", e);
+                }
+                if let Err(e) = list.reload(&db).await {
+                    tracing::warn!("Unable to reload revoked tokens. Error was: {}", e);
+                }
+            }
+        });
+    }
+}
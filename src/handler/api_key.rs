@@ -0,0 +1,121 @@
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    application::ApplicationState, database::entity::api_key::ApiKey, driver::db,
+    middleware::authentication::JwtClaims,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct CreateApiKey {
+    pub name: String,
+    /// Must be a subset of the permissions of the creating user.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Keys without expiration are valid until revoked.
+    pub expires_in_seconds: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CreatedApiKey {
+    /// The key itself, only ever returned on creation.
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+}
+
+/// Returns the claims of a user, who is logged in with a JWT. API keys
+/// can't manage API keys.
+fn user_claims(claims: Option<JwtClaims>) -> Result<JwtClaims, StatusCode> {
+    match claims {
+        Some(claims) if claims.api_key.is_none() => Ok(claims),
+        Some(_) => Err(StatusCode::FORBIDDEN),
+        None => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+/// Lists all API keys of the user, incl. revoked and expired ones.
+pub async fn list_api_keys(
+    Extension(claims): Extension<Option<JwtClaims>>,
+    Extension(db): Extension<db::DB>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let claims = user_claims(claims)?;
+
+    let api_keys = ApiKey::list_for_subject(&claims.sub, &db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Unable to list API keys. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(api_keys))
+}
+
+/// Creates an API key for the user. The key is only part of this response.
+pub async fn create_api_key(
+    Extension(claims): Extension<Option<JwtClaims>>,
+    Extension(state): Extension<ApplicationState>,
+    Extension(db): Extension<db::DB>,
+    Json(request): Json<CreateApiKey>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let claims = user_claims(claims)?;
+
+    if let Some(scope) = request.scopes.iter().find(|s| !claims.has_permission(s)) {
+        tracing::warn!(
+            "User {} is not allowed to create an API key with scope '{}'",
+            claims.sub,
+            scope
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let expires_at = match request.expires_in_seconds {
+        Some(seconds) if seconds <= 0 => return Err(StatusCode::BAD_REQUEST),
+        Some(seconds) => Some(chrono::Utc::now() + chrono::Duration::seconds(seconds)),
+        None => None,
+    };
+
+    let (key, api_key) = ApiKey::generate(
+        &state.configuration.application.auth.api_key_prefix,
+        &claims.sub,
+        &request.name,
+        request.scopes,
+        expires_at,
+    )
+    .map_err(|e| {
+        tracing::error!("Unable to generate API key. Error was: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    api_key.insert(&db).await.map_err(|e| {
+        tracing::error!("Unable to store API key. Error was: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok((StatusCode::CREATED, Json(CreatedApiKey { key, api_key })))
+}
+
+pub async fn revoke_api_key(
+    Path(id): Path<Uuid>,
+    Extension(claims): Extension<Option<JwtClaims>>,
+    Extension(db): Extension<db::DB>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let claims = user_claims(claims)?;
+
+    let revoked = ApiKey::revoke(id, &claims.sub, &db).await.map_err(|e| {
+        tracing::error!("Unable to revoke API key. Error was: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if revoked {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
@@ -1,38 +1,172 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use anyhow::anyhow;
 use axum::{
     extract::{Path, Query},
-    http::StatusCode,
-    response::Redirect,
+    http::{HeaderMap, StatusCode},
+    response::{AppendHeaders, Redirect},
+    TypedHeader,
     {extract::Extension, response::IntoResponse, Json},
 };
 
+use headers::Cookie;
 use hyper::header::{LOCATION, SET_COOKIE};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use openidconnect::url::{form_urlencoded, Url};
 use openidconnect::{
-    core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata, CoreTokenResponse},
+    core::{CoreAuthenticationFlow, CoreClient, CoreTokenResponse},
     reqwest::async_http_client,
-    AuthorizationCode, ClientId, ClientSecret, CsrfToken, EndUserEmail, EndUserFamilyName,
-    EndUserGivenName, EndUserPictureUrl, IssuerUrl, LocalizedClaim, Nonce, NonceVerifier,
-    RedirectUrl, Scope,
+    AuthorizationCode, ClientId, ClientSecret, CsrfToken, Nonce, OAuth2TokenResponse,
+    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, RefreshToken, Scope,
 };
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
 use tracing::debug;
+use uuid::Uuid;
 
 use crate::{
     application::ApplicationState,
+    configuration::{Auth, Configuration, Oidc},
+    crypto,
+    database::entity::{oidc_session::OidcSession, user_identity::UserIdentity},
     driver::db,
-    middleware::authentication::{create_jwt_from_claims, JwtClaims},
+    middleware::{
+        authentication::{
+            create_jwt_from_claims, jwt_from_headers, verify_expired_token, JwtClaims,
+        },
+        extract::AuthUser,
+    },
+    return_to,
 };
 
+/// Purpose used for deriving the refresh token encryption key.
+const REFRESH_TOKEN_PURPOSE: &str = "oidc_refresh_token";
+
+/// Purpose used for deriving the login state signing key, so a login state
+/// is never accepted as HS256 JWT and vice versa.
+const LOGIN_STATE_PURPOSE: &str = "oidc_login_state";
+
+/// State of a running OIDC login, which is kept in a signed, short lived
+/// cookie between `oidc_client_login` and `oidc_client_login_cb`.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct OidcLoginState {
+    pub provider: String,
+    pub csrf_token: String,
+    pub nonce: String,
+    pub pkce_verifier: Option<String>,
+    /// Allowed target to send the user to after the login.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_to: Option<String>,
+    /// User, to whose account the provider identity is linked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_user: Option<Uuid>,
+    pub exp: usize,
+}
+
+impl OidcLoginState {
+    pub fn new(
+        provider: &str,
+        csrf_token: &CsrfToken,
+        nonce: &Nonce,
+        pkce_verifier: Option<&PkceCodeVerifier>,
+        return_to: Option<String>,
+        link_user: Option<Uuid>,
+        exp_offset: usize,
+    ) -> Self {
+        Self {
+            provider: provider.to_string(),
+            csrf_token: csrf_token.secret().to_string(),
+            nonce: nonce.secret().to_string(),
+            pkce_verifier: pkce_verifier.map(|v| v.secret().to_string()),
+            return_to,
+            link_user,
+            exp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as usize
+                + exp_offset,
+        }
+    }
+
+    /// Signs the login state with a key derived from the `jwt_secret`.
+    pub fn encode(&self, auth: &Auth) -> anyhow::Result<String> {
+        let key =
+            EncodingKey::from_secret(&crypto::derive_key(&auth.jwt_secret, LOGIN_STATE_PURPOSE));
+        encode(&Header::new(Algorithm::HS256), self, &key).map_err(Into::into)
+    }
+
+    /// Verifies signature and expiration of an encoded login state.
+    pub fn decode(token: &str, auth: &Auth) -> anyhow::Result<Self> {
+        let key =
+            DecodingKey::from_secret(&crypto::derive_key(&auth.jwt_secret, LOGIN_STATE_PURPOSE));
+        Ok(decode::<Self>(token, &key, &Validation::new(Algorithm::HS256))?.claims)
+    }
+}
+
+/// Builds a `Set-Cookie` header value, which is `secure` as configured or
+/// with TLS. A `max_age` of `Some(0)` expires the cookie immediately.
+pub(crate) fn build_cookie(
+    cfg: &Configuration,
+    name: &str,
+    value: &str,
+    path: &str,
+    max_age: Option<usize>,
+) -> String {
+    format!(
+        "{}={}; path={}{}; HttpOnly; SameSite=lax{}",
+        name,
+        value,
+        path,
+        max_age
+            .map(|m| format!("; Max-Age={}", m))
+            .unwrap_or_default(),
+        if cfg.application.auth.cookie_secure(&cfg.http) {
+            "; secure;"
+        } else {
+            ";"
+        }
+    )
+}
+
 pub async fn claims(Extension(claims): Extension<Option<JwtClaims>>) -> impl IntoResponse {
     Json(claims)
 }
 
-/// Oidc client creation helper
+/// Returns the internal user id of a user, who is logged in with a JWT.
+fn linkable_user(claims: &JwtClaims) -> Result<Uuid, StatusCode> {
+    if claims.api_key.is_some() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    claims.user_id().map_err(|e| {
+        tracing::warn!("JWT 'sub' {} is no user id. Error was: {}", claims.sub, e);
+        StatusCode::FORBIDDEN
+    })
+}
+
+/// Lists the provider identities linked to the account of the user.
+pub async fn list_identities(
+    AuthUser(claims): AuthUser,
+    Extension(db): Extension<db::DB>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let user_id = linkable_user(&claims)?;
+
+    let identities = UserIdentity::list_for_user(user_id, &db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Unable to list user identities. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(identities))
+}
+
+/// Oidc client creation helper, which also returns the provider configuration
 async fn oidc_client(
     provider_name: &str,
     state: &ApplicationState,
-) -> Result<CoreClient, StatusCode> {
+) -> Result<(Oidc, CoreClient), StatusCode> {
     let provider = state
         .configuration
         .get_oidc_provider(provider_name)
@@ -41,17 +175,9 @@ async fn oidc_client(
             StatusCode::NOT_FOUND
         })?;
 
-    // Use OpenID Connect Discovery to fetch the provider metadata.
-    let provider_metadata = CoreProviderMetadata::discover_async(
-        IssuerUrl::new(provider.issuer_url.clone()).map_err(|e| {
-            tracing::error!("Parsing error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?,
-        async_http_client,
-    )
-    .await
-    .map_err(|e| {
-        tracing::error!("Discovery Error: {}", e);
+    // Use the (cached) OpenID Connect Discovery to get the provider metadata.
+    let provider_metadata = state.oidc_metadata.get(&provider).await.map_err(|e| {
+        tracing::error!("{}", e);
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
@@ -62,7 +188,7 @@ async fn oidc_client(
     let client = CoreClient::from_provider_metadata(
         provider_metadata,
         ClientId::new(provider.client_id.to_string()),
-        Some(ClientSecret::new(provider.client_secret.to_string())),
+        provider.client_secret.clone().map(ClientSecret::new),
     )
     // Set the URL the user will be redirected to after the authorization process.
     .set_redirect_uri(
@@ -73,55 +199,273 @@ async fn oidc_client(
     );
 
     tracing::debug!("Oidc Client Information: {:?}", client);
-    Ok(client)
+    Ok((provider, client))
 }
 
-/// Callback handler for openid connect
+/// Callback handler for openid connect. An optional `return_to` query
+/// parameter is where the user is sent after a successful login.
 pub async fn oidc_client_login(
+    Query(query): Query<HashMap<String, String>>,
     Path(provider_name): Path<String>,
     Extension(_claims): Extension<Option<JwtClaims>>,
     Extension(state): Extension<ApplicationState>,
-) -> Result<Redirect, StatusCode> {
-    let client = oidc_client(&provider_name, &state).await?;
-    let (auth_url, _csrf_token, _nonce) = client
-        .authorize_url(
-            CoreAuthenticationFlow::AuthorizationCode,
-            CsrfToken::new_random,
-            Nonce::new_random,
-        )
-        // TODO: use config client_scopes value
-        .add_scope(Scope::new("email".into()))
-        .add_scope(Scope::new("profile".into()))
-        .url();
+) -> Result<impl IntoResponse, StatusCode> {
+    let return_to = return_to::validated(
+        &state.configuration.application.auth,
+        query.get("return_to"),
+    );
+    start_oidc_login(&provider_name, return_to, None, &state).await
+}
 
-    Ok(Redirect::to(auth_url.as_str()))
+/// Starts an OIDC login, which links the identity of the provider to the
+/// account of the logged in user. Afterwards, both logins issue JWTs for
+/// the same `sub`.
+pub async fn oidc_client_link(
+    Query(query): Query<HashMap<String, String>>,
+    Path(provider_name): Path<String>,
+    AuthUser(claims): AuthUser,
+    Extension(state): Extension<ApplicationState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let user_id = linkable_user(&claims)?;
+    let return_to = return_to::validated(
+        &state.configuration.application.auth,
+        query.get("return_to"),
+    );
+    start_oidc_login(&provider_name, return_to, Some(user_id), &state).await
 }
 
-// TODO: Actually store this nonce somewhere
-pub struct NoNonce();
+/// Redirects to the authorization endpoint of the provider and keeps the
+/// login state in a cookie.
+async fn start_oidc_login(
+    provider_name: &str,
+    return_to: Option<String>,
+    link_user: Option<Uuid>,
+    state: &ApplicationState,
+) -> Result<impl IntoResponse, StatusCode> {
+    let auth = &state.configuration.application.auth;
+    let (provider, client) = oidc_client(provider_name, state).await?;
+    let mut auth_request = client.authorize_url(
+        CoreAuthenticationFlow::AuthorizationCode,
+        CsrfToken::new_random,
+        Nonce::new_random,
+    );
 
-impl NonceVerifier for &NoNonce {
-    fn verify(self, _nonce: Option<&Nonce>) -> Result<(), String> {
-        Ok(())
+    // The `openid` scope is always requested by the client itself.
+    for scope in provider
+        .client_scopes
+        .iter()
+        .flat_map(|s| s.split_whitespace())
+        .filter(|s| *s != "openid")
+    {
+        auth_request = auth_request.add_scope(Scope::new(scope.to_string()));
+    }
+
+    for (name, value) in provider.authorization_params.iter() {
+        auth_request = auth_request.add_extra_param(name.clone(), value.clone());
+    }
+
+    // PKCE (S256), the verifier is kept in the login state until the code exchange.
+    let pkce_verifier = if provider.pkce {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        auth_request = auth_request.set_pkce_challenge(pkce_challenge);
+        Some(pkce_verifier)
+    } else {
+        None
+    };
+
+    let (auth_url, csrf_token, nonce) = auth_request.url();
+
+    let login_state = OidcLoginState::new(
+        provider_name,
+        &csrf_token,
+        &nonce,
+        pkce_verifier.as_ref(),
+        return_to,
+        link_user,
+        auth.oidc_state_expiration_seconds,
+    )
+    .encode(auth)
+    .map_err(|e| {
+        tracing::error!("Unable to encode oidc login state. Error was: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let cookie = build_cookie(
+        &state.configuration,
+        &auth.oidc_state_cookie_name,
+        &login_state,
+        &auth.path_prefix,
+        Some(auth.oidc_state_expiration_seconds),
+    );
+
+    Ok(([(SET_COOKIE, cookie)], Redirect::to(auth_url.as_str())))
+}
+
+/// Failure of the OIDC login callback. The `code` is handed to the
+/// `redirect_on_login_error` page as `error` query parameter.
+#[derive(Debug)]
+pub(crate) struct LoginError {
+    pub code: &'static str,
+    /// The `error` returned by the provider itself, e.g. `access_denied`.
+    pub provider_error: Option<String>,
+}
+
+impl LoginError {
+    fn new(code: &'static str) -> Self {
+        Self {
+            code,
+            provider_error: None,
+        }
+    }
+
+    /// Appends the error code(s) to the configured error page.
+    fn redirect_url(&self, redirect_on_login_error: &str) -> String {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        query.append_pair("error", self.code);
+        if let Some(provider_error) = &self.provider_error {
+            query.append_pair("provider_error", provider_error);
+        }
+
+        let separator = if redirect_on_login_error.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        format!("{}{}{}", redirect_on_login_error, separator, query.finish())
     }
 }
 
+/// Callback handler for openid connect. Redirects to
+/// `redirect_on_login_success` with the JWT cookie set, or to
+/// `redirect_on_login_error` with an error code on any failure.
 pub async fn oidc_client_login_cb(
     Query(query): Query<HashMap<String, String>>,
     Path(provider_name): Path<String>,
+    cookies: Option<TypedHeader<Cookie>>,
     Extension(_claims): Extension<Option<JwtClaims>>,
     Extension(state): Extension<ApplicationState>,
     Extension(db): Extension<db::DB>,
-) -> Result<impl IntoResponse, StatusCode> {
+) -> impl IntoResponse {
     tracing::info!("{:?}", query);
-    let client = oidc_client(&provider_name, &state).await?;
+    let auth = &state.configuration.application.auth;
+
+    // The login state is single use, regardless of the outcome.
+    let state_cookie = build_cookie(
+        &state.configuration,
+        &auth.oidc_state_cookie_name,
+        "",
+        &auth.path_prefix,
+        Some(0),
+    );
+
+    let result = complete_oidc_login(&query, &provider_name, cookies.as_ref(), &state, &db).await;
+
+    // Unknown provider names must not create a time series each.
+    let provider_label = if auth.oidc.contains_key(&provider_name) {
+        provider_name.as_str()
+    } else {
+        "unknown"
+    };
+    state.metrics.record_oidc_login(
+        provider_label,
+        result.as_ref().map_or_else(|e| e.code, |_| "success"),
+    );
+
+    match result {
+        Ok((jwt, return_to)) => {
+            let cookie = build_cookie(&state.configuration, &auth.jwt_cookie_name, &jwt, "/", None);
+            debug!("Setting cookie: '{}'", cookie);
+
+            let h = AppendHeaders([
+                (SET_COOKIE, cookie),
+                (SET_COOKIE, state_cookie),
+                (
+                    LOCATION,
+                    return_to.unwrap_or_else(|| auth.redirect_on_login_success.clone()),
+                ),
+            ]);
+            (h, StatusCode::FOUND).into_response()
+        }
+        Err(e) => {
+            tracing::warn!("Oidc login with provider {} failed: {:?}", provider_name, e);
+            if let Err(e) = state
+                .handlers
+                .on_login_failure(&provider_name, e.code, &state.configuration, db.clone())
+                .await
+            {
+                tracing::error!("on_login_failure handler failed. Error was: {}", e);
+            }
+
+            let h = AppendHeaders([
+                (SET_COOKIE, state_cookie),
+                (LOCATION, e.redirect_url(&auth.redirect_on_login_error)),
+            ]);
+            (h, StatusCode::FOUND).into_response()
+        }
+    }
+}
+
+/// Validates the callback request, exchanges the code and returns the JWT
+/// for the logged in user and the `return_to` target of the login.
+async fn complete_oidc_login(
+    query: &HashMap<String, String>,
+    provider_name: &str,
+    cookies: Option<&TypedHeader<Cookie>>,
+    state: &ApplicationState,
+    db: &db::DB,
+) -> Result<(String, Option<String>), LoginError> {
+    let auth = &state.configuration.application.auth;
+
+    let login_state = cookies
+        .and_then(|TypedHeader(c)| c.get(&auth.oidc_state_cookie_name))
+        .ok_or_else(|| {
+            tracing::error!("Missing oidc login state cookie");
+            LoginError::new("invalid_state")
+        })?;
+    let login_state = OidcLoginState::decode(login_state, auth).map_err(|e| {
+        tracing::error!("Invalid oidc login state cookie. Error was: {}", e);
+        LoginError::new("invalid_state")
+    })?;
+
+    let csrf_token = query.get("state").ok_or_else(|| {
+        tracing::error!("Missing request query parameter 'state'");
+        LoginError::new("invalid_state")
+    })?;
+    if login_state.provider != provider_name || &login_state.csrf_token != csrf_token {
+        tracing::error!("Request query parameter 'state' does not match the oidc login state");
+        return Err(LoginError::new("invalid_state"));
+    }
+
+    if let Some(error) = query.get("error") {
+        tracing::warn!(
+            "Provider returned error: {}, description: {:?}",
+            error,
+            query.get("error_description")
+        );
+        return Err(LoginError {
+            code: "provider_error",
+            provider_error: Some(error.clone()),
+        });
+    }
+
+    let (provider, client) = oidc_client(provider_name, state).await.map_err(|status| {
+        LoginError::new(if status == StatusCode::NOT_FOUND {
+            "unknown_provider"
+        } else {
+            "provider_unavailable"
+        })
+    })?;
     let code = query.get("code").ok_or_else(|| {
         tracing::error!("Missing request query parameter 'code'");
-        StatusCode::BAD_REQUEST
+        LoginError::new("missing_code")
     })?;
 
-    let token_response: CoreTokenResponse = client
-        .exchange_code(AuthorizationCode::new(code.to_string()))
+    let mut token_request = client.exchange_code(AuthorizationCode::new(code.to_string()));
+    if let Some(pkce_verifier) = login_state.pkce_verifier.clone() {
+        token_request = token_request.set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier));
+    }
+
+    let token_response: CoreTokenResponse = token_request
         .request_async(async_http_client)
         .await
         .map_err(|e| {
@@ -129,64 +473,69 @@ pub async fn oidc_client_login_cb(
                 "Request Token Error received for code: {code}. Error was: {:?}",
                 e
             );
-            StatusCode::INTERNAL_SERVER_ERROR
+            LoginError::new("token_exchange_failed")
         })?;
 
     tracing::info!("Got valid Token Response: {:?}", token_response);
 
-    let id_token = token_response
-        .extra_fields()
-        .id_token()
-        .ok_or(anyhow!(
-            "No ID token provided from provider: {provider_name}"
-        ))
-        .unwrap();
+    let id_token = token_response.extra_fields().id_token().ok_or_else(|| {
+        tracing::error!("No ID token provided from provider: {provider_name}");
+        LoginError::new("missing_id_token")
+    })?;
 
     let provider_claims = id_token
-        .claims(&client.id_token_verifier(), &NoNonce())
-        .unwrap()
+        .claims(
+            &client.id_token_verifier(),
+            &Nonce::new(login_state.nonce.clone()),
+        )
+        .map_err(|e| {
+            tracing::error!("Invalid ID token. Error was: {}", e);
+            LoginError::new("invalid_id_token")
+        })?
         .clone();
 
+    // The identity is only stored, if the `on_login_callback` accepts the login.
+    let mut tx = db.begin().await.map_err(|e| {
+        tracing::error!("Unable to start transaction. Error was: {}", e);
+        LoginError::new("server_error")
+    })?;
+    let user_id = resolve_identity(
+        provider_name,
+        provider_claims.subject(),
+        login_state.link_user,
+        &mut tx,
+    )
+    .await?;
+
+    // Not every provider (or scope) delivers the profile claims.
     let mut claims = JwtClaims::new(
-        provider_claims.subject().to_string(),
-        state.configuration.http.full_base_url(),
+        user_id.to_string(),
+        auth.issuer(&state.configuration.http),
         provider_claims
             .email()
-            .unwrap_or(&EndUserEmail::new("".to_string()))
-            .to_string(),
+            .map(|e| e.to_string())
+            .unwrap_or_default(),
         provider_claims
             .given_name()
-            .unwrap_or(&LocalizedClaim::<EndUserGivenName>::default())
-            .get(None)
-            .ok_or_else(|| {
-                tracing::error!("Unable to extract given_name from id token.");
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?
-            .to_string(),
+            .and_then(|n| n.get(None))
+            .map(|n| n.to_string())
+            .unwrap_or_default(),
         provider_claims
             .family_name()
-            .unwrap_or(&LocalizedClaim::<EndUserFamilyName>::default())
-            .get(None)
-            .ok_or_else(|| {
-                tracing::error!("Unable to extract given_name from id token.");
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?
-            .to_string(),
+            .and_then(|n| n.get(None))
+            .map(|n| n.to_string())
+            .unwrap_or_default(),
         provider_claims
             .picture()
-            .unwrap_or(&LocalizedClaim::<EndUserPictureUrl>::default())
-            .get(None)
+            .and_then(|p| p.get(None))
             .map(|p| p.to_string()),
-        state
-            .configuration
-            .application
-            .auth
-            .jwt_expiration_offset_seconds,
+        auth.jwt_expiration_offset_seconds,
     );
+    claims.client_role = Some(provider.client_role.clone());
 
     state
         .handlers
-        .on_login_callback(&mut claims, &state.configuration, db)
+        .on_login_callback(&mut claims, &state.configuration, db.clone())
         .await
         .map_err(|e| {
             tracing::error!(
@@ -194,36 +543,365 @@ pub async fn oidc_client_login_cb(
                 claims,
                 e
             );
-            StatusCode::INTERNAL_SERVER_ERROR
+            LoginError::new("login_rejected")
         })?;
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Unable to store user identity. Error was: {}", e);
+        LoginError::new("server_error")
+    })?;
+
+    let sid = store_session(
+        provider_name,
+        provider_claims.subject(),
+        token_response.refresh_token(),
+        id_token.to_string(),
+        auth,
+        db,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("Unable to store oidc session. Error was: {}", e);
+        LoginError::new("server_error")
+    })?;
+    claims.sid = Some(sid.to_string());
+
+    let jwt =
+        create_jwt_from_claims(&state.configuration, &state.keys, claims, None).map_err(|e| {
+            tracing::error!("Unable to create JWT. Error was: {}", e);
+            LoginError::new("server_error")
+        })?;
+
+    // The allowlist may have changed since the login started.
+    Ok((
+        jwt,
+        return_to::validated(auth, login_state.return_to.as_ref()),
+    ))
+}
 
-    let cookie = format!(
-        "{}={}; path=/; HttpOnly; SameSite=lax{}",
-        &state.configuration.application.auth.jwt_cookie_name,
-        create_jwt_from_claims(&state.configuration, claims, None).unwrap(),
-        &state
-            .configuration
-            .application
-            .auth
-            .jwt_cookie_secure
-            .then(|| "; secure;")
-            .unwrap_or(";")
+/// Maps the provider identity to the internal user id. Unknown identities
+/// belong to a new user or, when linking, to `link_user`. Nothing is stored
+/// until the transaction is committed.
+async fn resolve_identity(
+    provider_name: &str,
+    subject: &str,
+    link_user: Option<Uuid>,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<Uuid, LoginError> {
+    let server_error = |e: anyhow::Error| {
+        tracing::error!("Unable to resolve user identity. Error was: {}", e);
+        LoginError::new("server_error")
+    };
+
+    let link_user = match link_user {
+        Some(link_user) => link_user,
+        None => {
+            return UserIdentity::get_or_create(provider_name, subject, tx)
+                .await
+                .map(|identity| identity.user_id)
+                .map_err(server_error)
+        }
+    };
+
+    if !UserIdentity::link(provider_name, subject, link_user, tx)
+        .await
+        .map_err(server_error)?
+    {
+        tracing::warn!(
+            "Identity {} of provider {} is already linked to another user",
+            subject,
+            provider_name
+        );
+        return Err(LoginError::new("identity_already_linked"));
+    }
+
+    tracing::info!(
+        "Linked identity {} of provider {} to user {}",
+        subject,
+        provider_name,
+        link_user
     );
+    Ok(link_user)
+}
+
+/// Stores the oidc session incl. the encrypted refresh token of the provider
+/// and returns its id.
+async fn store_session(
+    provider_name: &str,
+    subject: &str,
+    refresh_token: Option<&RefreshToken>,
+    id_token: String,
+    auth: &Auth,
+    db: &db::DB,
+) -> anyhow::Result<Uuid> {
+    let now = chrono::Utc::now();
+    let stored = OidcSession {
+        id: Uuid::new_v4(),
+        provider_name: provider_name.to_string(),
+        subject: subject.to_string(),
+        refresh_token: refresh_token
+            .map(|t| {
+                crypto::encrypt(
+                    &auth.jwt_secret,
+                    REFRESH_TOKEN_PURPOSE,
+                    t.secret().as_bytes(),
+                )
+            })
+            .transpose()?,
+        id_token: Some(id_token),
+        created_at: now,
+        expires_at: now + chrono::Duration::seconds(auth.refresh_token_expiration_seconds as i64),
+    };
+    stored.insert(db).await?;
+
+    Ok(stored.id)
+}
+
+#[derive(Serialize, Debug)]
+pub struct TokenResponse {
+    pub token: String,
+}
+
+/// Exchanges a (possibly expired) JWT, which carries a `sid`, against a fresh
+/// one by using the stored refresh token of the OIDC provider.
+pub async fn refresh(
+    headers: HeaderMap,
+    Extension(state): Extension<ApplicationState>,
+    Extension(db): Extension<db::DB>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let app_config = &state.configuration.application;
+    let auth = &app_config.auth;
+
+    let token = jwt_from_headers(&headers, app_config).map_err(|e| {
+        tracing::warn!("Token refresh failed: {}", e);
+        StatusCode::UNAUTHORIZED
+    })?;
+    let mut claims = verify_expired_token(&token, &state.configuration, &state.keys)
+        .await
+        .map_err(|e| {
+            tracing::warn!("Token refresh failed: {}", e);
+            StatusCode::UNAUTHORIZED
+        })?
+        .claims;
+
+    if state.revocations.is_revoked(&claims).await {
+        tracing::warn!("Token refresh failed: JWT is revoked");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let sid = claims
+        .sid
+        .as_deref()
+        .and_then(|sid| Uuid::parse_str(sid).ok())
+        .ok_or_else(|| {
+            tracing::warn!("Token refresh failed: JWT carries no valid 'sid'");
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let stored = OidcSession::get_valid(sid, &db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Unable to load oidc session. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .filter(|s| s.refresh_token.is_some())
+        .ok_or_else(|| {
+            tracing::warn!(
+                "Token refresh failed: No valid refresh token for sid {}",
+                sid
+            );
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let refresh_token = crypto::decrypt(
+        &auth.jwt_secret,
+        REFRESH_TOKEN_PURPOSE,
+        stored.refresh_token.as_deref().unwrap_or_default(),
+    )
+    .and_then(|t| Ok(String::from_utf8(t)?))
+    .map_err(|e| {
+        tracing::error!("Unable to decrypt refresh token. Error was: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let (_, client) = oidc_client(&stored.provider_name, &state).await?;
+    let token_response: CoreTokenResponse = client
+        .exchange_refresh_token(&RefreshToken::new(refresh_token))
+        .request_async(async_http_client)
+        .await
+        .map_err(|e| {
+            tracing::warn!("Provider rejected refresh token. Error was: {:?}", e);
+            StatusCode::UNAUTHORIZED
+        })?;
 
-    debug!("Setting cookie: '{}'", cookie);
+    // Providers may rotate the refresh token on every use.
+    if let Some(refresh_token) = token_response.refresh_token() {
+        let encrypted = crypto::encrypt(
+            &auth.jwt_secret,
+            REFRESH_TOKEN_PURPOSE,
+            refresh_token.secret().as_bytes(),
+        )
+        .map_err(|e| {
+            tracing::error!("Unable to encrypt refresh token. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+        OidcSession::update_refresh_token(sid, encrypted, &db)
+            .await
+            .map_err(|e| {
+                tracing::error!("Unable to store refresh token. Error was: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+    }
 
-    let h = [
-        (SET_COOKIE, cookie),
+    claims.renew(auth.jwt_expiration_offset_seconds);
+    state
+        .handlers
+        .on_token_refresh(&mut claims, &state.configuration, db.clone())
+        .await
+        .map_err(|e| {
+            tracing::warn!(
+                "on_token_refresh handler rejected claims: {:?}. Error was: {}",
+                claims,
+                e
+            );
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let jwt =
+        create_jwt_from_claims(&state.configuration, &state.keys, claims, None).map_err(|e| {
+            tracing::error!("Unable to create JWT. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let cookie = build_cookie(&state.configuration, &auth.jwt_cookie_name, &jwt, "/", None);
+
+    Ok(([(SET_COOKIE, cookie)], Json(TokenResponse { token: jwt })))
+}
+
+/// Logs the user out by expiring the JWT cookie, revoking the JWT and
+/// deleting the oidc session.
+/// If a `post_logout_redirect_url` is configured for the provider and it
+/// supports RP-Initiated Logout, the user is sent through the provider's end
+/// session endpoint, otherwise directly to `redirect_on_logout`.
+/// Only accepts `POST`, the `SameSite=lax` JWT cookie isn't sent along with
+/// cross-site posts, so other sites can't log users out.
+pub async fn logout(
+    headers: HeaderMap,
+    Extension(state): Extension<ApplicationState>,
+    Extension(db): Extension<db::DB>,
+) -> impl IntoResponse {
+    let app_config = &state.configuration.application;
+    let auth = &app_config.auth;
+
+    let session = end_session(&headers, &state, &db)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::debug!("No oidc session ended on logout: {}", e);
+            None
+        });
+
+    let provider_logout_url = match session {
+        Some(session) => provider_logout_url(&session, &state)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Unable to build provider logout url. Error was: {}", e);
+                None
+            }),
+        None => None,
+    };
+
+    let h = AppendHeaders([
+        (
+            SET_COOKIE,
+            build_cookie(
+                &state.configuration,
+                &auth.jwt_cookie_name,
+                "",
+                "/",
+                Some(0),
+            ),
+        ),
         (
             LOCATION,
-            state
-                .configuration
-                .application
-                .auth
-                .redirect_on_login_success
-                .clone(),
+            provider_logout_url.unwrap_or_else(|| auth.redirect_on_logout.clone()),
         ),
-    ];
+    ]);
+
+    (h, StatusCode::SEE_OTHER)
+}
+
+/// Revokes the (possibly expired) JWT, deletes the oidc session it
+/// references and notifies `AppHandler::on_logout`.
+async fn end_session(
+    headers: &HeaderMap,
+    state: &ApplicationState,
+    db: &db::DB,
+) -> anyhow::Result<Option<OidcSession>> {
+    let app_config = &state.configuration.application;
+    let token = jwt_from_headers(headers, app_config)?;
+    let claims = verify_expired_token(&token, &state.configuration, &state.keys)
+        .await?
+        .claims;
+
+    if claims.jti.is_some() {
+        state
+            .revocations
+            .revoke_token(&claims, &app_config.auth, db)
+            .await?;
+    }
+
+    if let Err(e) = state
+        .handlers
+        .on_logout(&claims, &state.configuration, db.clone())
+        .await
+    {
+        tracing::error!("on_logout handler failed. Error was: {}", e);
+    }
+
+    let sid = match claims.sid.as_deref() {
+        Some(sid) => Uuid::parse_str(sid)?,
+        None => return Ok(None),
+    };
+
+    let session = OidcSession::get(sid, db).await?;
+    OidcSession::delete(sid, db).await?;
+
+    Ok(session)
+}
+
+/// Builds the RP-Initiated Logout URL for the provider of a session.
+async fn provider_logout_url(
+    session: &OidcSession,
+    state: &ApplicationState,
+) -> anyhow::Result<Option<String>> {
+    let provider = match state
+        .configuration
+        .get_oidc_provider(&session.provider_name)
+    {
+        Some(provider) => provider,
+        None => return Ok(None),
+    };
+
+    let post_logout_redirect_url = match &provider.post_logout_redirect_url {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+
+    let metadata = state.oidc_metadata.get(&provider).await?;
+    let end_session_endpoint = match &metadata.additional_metadata().end_session_endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(None),
+    };
+
+    let mut url = Url::parse(end_session_endpoint)?;
+    {
+        let mut query = url.query_pairs_mut();
+        if let Some(id_token) = &session.id_token {
+            query.append_pair("id_token_hint", id_token);
+        }
+        query.append_pair("client_id", &provider.client_id);
+        query.append_pair("post_logout_redirect_uri", post_logout_redirect_url);
+    }
 
-    Ok((h, StatusCode::FOUND))
+    Ok(Some(url.to_string()))
 }
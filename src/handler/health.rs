@@ -0,0 +1,48 @@
+use std::collections::BTreeMap;
+
+use axum::{extract::Extension, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+
+use crate::{
+    application::ApplicationState,
+    driver::db,
+    health::{readiness, CheckResult},
+};
+
+#[derive(Serialize, Debug)]
+pub struct Health {
+    pub status: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Readiness {
+    pub status: &'static str,
+    pub checks: BTreeMap<String, CheckResult>,
+}
+
+/// Liveness probe, which only reports that the process is up.
+pub async fn healthz() -> impl IntoResponse {
+    Json(Health { status: "ok" })
+}
+
+/// Readiness probe with the status of every check. Responds with a 503, if
+/// any check failed.
+pub async fn readyz(
+    Extension(state): Extension<ApplicationState>,
+    Extension(db): Extension<db::DB>,
+) -> impl IntoResponse {
+    let results = readiness(&state, &db).await;
+    let ready = results.iter().all(CheckResult::is_ok);
+
+    let readiness = Readiness {
+        status: if ready { "ready" } else { "not_ready" },
+        checks: results.into_iter().map(|r| (r.name.clone(), r)).collect(),
+    };
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(readiness))
+}
@@ -0,0 +1,390 @@
+use axum::{
+    extract::Extension,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use hyper::header::SET_COOKIE;
+use once_cell::sync::Lazy;
+use openssl::sha::sha256;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    application::ApplicationState,
+    configuration::LocalAuth,
+    crypto,
+    database::entity::{
+        local_credential::LocalCredential, oidc_session::OidcSession,
+        password_reset_token::PasswordResetToken,
+    },
+    driver::db,
+    handler::authentication::{build_cookie, TokenResponse},
+    middleware::authentication::{create_jwt_from_claims, JwtClaims},
+};
+
+/// Verified instead of a stored hash for unknown users, so the response time
+/// doesn't tell which email addresses are registered.
+static DUMMY_HASH: Lazy<String> =
+    Lazy::new(|| crypto::hash_password("aurelia-dummy-password").unwrap_or_default());
+
+#[derive(Deserialize, Debug)]
+pub struct Credentials {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangePassword {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PasswordReset {
+    pub token: String,
+    pub new_password: String,
+}
+
+fn local_auth(state: &ApplicationState) -> Result<&LocalAuth, StatusCode> {
+    let local = &state.configuration.application.auth.local;
+    if local.enabled {
+        Ok(local)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+fn check_password(local: &LocalAuth, password: &str) -> Result<(), StatusCode> {
+    if password.chars().count() < local.min_password_length {
+        tracing::warn!("Password is shorter than {}", local.min_password_length);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(())
+}
+
+fn hash_password(password: &str) -> Result<String, StatusCode> {
+    crypto::hash_password(password).map_err(|e| {
+        tracing::error!("Unable to hash password. Error was: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// A concurrent registration of the same email address won the race.
+fn is_unique_violation(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<sqlx::Error>(),
+        Some(sqlx::Error::Database(e)) if e.code().as_deref() == Some("23505")
+    )
+}
+
+/// Logs the user out everywhere after a password change, so whoever holds the
+/// old credentials loses access: All JWTs issued so far are revoked and the
+/// OIDC sessions (of linked providers) can't be refreshed anymore.
+async fn revoke_sessions(
+    user_id: Uuid,
+    state: &ApplicationState,
+    db: &db::DB,
+) -> Result<(), StatusCode> {
+    let server_error = |e: anyhow::Error| {
+        tracing::error!(
+            "Unable to revoke the sessions of user {}. Error was: {}",
+            user_id,
+            e
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    state
+        .revocations
+        .revoke_subject(
+            &user_id.to_string(),
+            &state.configuration.application.auth,
+            db,
+        )
+        .await
+        .map_err(server_error)?;
+    OidcSession::delete_for_user(user_id, db)
+        .await
+        .map_err(server_error)
+}
+
+async fn report_login_failure(error: &str, state: &ApplicationState, db: &db::DB) {
+    if let Err(e) = state
+        .handlers
+        .on_login_failure("local", error, &state.configuration, db.clone())
+        .await
+    {
+        tracing::error!("on_login_failure handler failed. Error was: {}", e);
+    }
+}
+
+/// Issues the JWT (as cookie and in the body) for a local user, the same
+/// way as after an OIDC login.
+async fn login_response(
+    credential: &LocalCredential,
+    state: &ApplicationState,
+    db: &db::DB,
+) -> Result<Response, StatusCode> {
+    let auth = &state.configuration.application.auth;
+
+    let mut claims = JwtClaims::new(
+        credential.user_id.to_string(),
+        auth.issuer(&state.configuration.http),
+        credential.email.clone(),
+        "".into(),
+        "".into(),
+        None,
+        auth.jwt_expiration_offset_seconds,
+    );
+    claims.client_role = Some(auth.local.client_role.clone());
+
+    if let Err(e) = state
+        .handlers
+        .on_login_callback(&mut claims, &state.configuration, db.clone())
+        .await
+    {
+        tracing::error!(
+            "on_login handler failed for claims: {:?}. Error was: {}",
+            claims,
+            e
+        );
+        report_login_failure("callback_rejected", state, db).await;
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let jwt =
+        create_jwt_from_claims(&state.configuration, &state.keys, claims, None).map_err(|e| {
+            tracing::error!("Unable to create JWT. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let cookie = build_cookie(&state.configuration, &auth.jwt_cookie_name, &jwt, "/", None);
+
+    Ok(([(SET_COOKIE, cookie)], Json(TokenResponse { token: jwt })).into_response())
+}
+
+pub async fn local_register(
+    Extension(state): Extension<ApplicationState>,
+    Extension(db): Extension<db::DB>,
+    Json(credentials): Json<Credentials>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let local = local_auth(&state)?;
+    if !local.allow_registration {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let email = normalize_email(&credentials.email);
+    if !email.contains('@') {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    check_password(local, &credentials.password)?;
+
+    let existing = LocalCredential::get_by_email(&email, &db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Unable to load local credential. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if existing.is_some() {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let credential = LocalCredential {
+        user_id: Uuid::new_v4(),
+        email,
+        password_hash: hash_password(&credentials.password)?,
+    };
+    credential.insert(&db).await.map_err(|e| {
+        if is_unique_violation(&e) {
+            tracing::warn!("Email address is already registered");
+            return StatusCode::CONFLICT;
+        }
+        tracing::error!("Unable to store local credential. Error was: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    // The `on_login_callback` sees the stored credential. It is deleted again,
+    // if the login isn't completed, e.g. because the callback rejects it.
+    let response = match login_response(&credential, &state, &db).await {
+        Ok(response) => response,
+        Err(status) => {
+            if let Err(e) = LocalCredential::delete(credential.user_id, &db).await {
+                tracing::error!(
+                    "Unable to delete local credential of rejected registration. Error was: {}",
+                    e
+                );
+            }
+            return Err(status);
+        }
+    };
+
+    Ok((StatusCode::CREATED, response))
+}
+
+pub async fn local_login(
+    Extension(state): Extension<ApplicationState>,
+    Extension(db): Extension<db::DB>,
+    Json(credentials): Json<Credentials>,
+) -> Result<impl IntoResponse, StatusCode> {
+    local_auth(&state)?;
+
+    let credential = LocalCredential::get_by_email(&normalize_email(&credentials.email), &db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Unable to load local credential. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    match credential {
+        Some(credential)
+            if crypto::verify_password(&credentials.password, &credential.password_hash) =>
+        {
+            return login_response(&credential, &state, &db).await;
+        }
+        Some(_) => {}
+        None => {
+            crypto::verify_password(&credentials.password, &DUMMY_HASH);
+        }
+    }
+
+    report_login_failure("invalid_credentials", &state, &db).await;
+    Err(StatusCode::UNAUTHORIZED)
+}
+
+/// Changes the password of the logged in local user and logs them out
+/// everywhere, see `revoke_sessions`.
+pub async fn local_change_password(
+    Extension(claims): Extension<Option<JwtClaims>>,
+    Extension(state): Extension<ApplicationState>,
+    Extension(db): Extension<db::DB>,
+    Json(request): Json<ChangePassword>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let local = local_auth(&state)?;
+
+    let user_id = claims
+        .filter(|c| c.api_key.is_none())
+        .and_then(|c| c.user_id().ok())
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let credential = LocalCredential::get(user_id, &db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Unable to load local credential. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if !crypto::verify_password(&request.current_password, &credential.password_hash) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    check_password(local, &request.new_password)?;
+
+    LocalCredential::update_password(
+        credential.user_id,
+        &hash_password(&request.new_password)?,
+        &db,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("Unable to update password. Error was: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    revoke_sessions(credential.user_id, &state, &db).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Creates a password reset token, which is delivered by
+/// `AppHandler::on_password_reset_requested`. Always accepted, so the
+/// response doesn't tell which email addresses are registered.
+pub async fn local_request_password_reset(
+    Extension(state): Extension<ApplicationState>,
+    Extension(db): Extension<db::DB>,
+    Json(request): Json<PasswordResetRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let local = local_auth(&state)?;
+
+    let credential = LocalCredential::get_by_email(&normalize_email(&request.email), &db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Unable to load local credential. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if let Some(credential) = credential {
+        let token = crypto::random_token().map_err(|e| {
+            tracing::error!("Unable to generate password reset token. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+        let now = chrono::Utc::now();
+        PasswordResetToken {
+            token_hash: sha256(token.as_bytes()).to_vec(),
+            user_id: credential.user_id,
+            created_at: now,
+            expires_at: now
+                + chrono::Duration::seconds(local.password_reset_expiration_seconds as i64),
+            used_at: None,
+        }
+        .insert(&db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Unable to store password reset token. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+        if let Err(e) = state
+            .handlers
+            .on_password_reset_requested(&credential, &token, &state.configuration)
+            .await
+        {
+            tracing::error!(
+                "Unable to deliver password reset token for user {}. Error was: {}",
+                credential.user_id,
+                e
+            );
+        }
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Sets a new password with a password reset token and logs the user out
+/// everywhere, see `revoke_sessions`.
+pub async fn local_reset_password(
+    Extension(state): Extension<ApplicationState>,
+    Extension(db): Extension<db::DB>,
+    Json(request): Json<PasswordReset>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let local = local_auth(&state)?;
+    check_password(local, &request.new_password)?;
+
+    let token = PasswordResetToken::consume(&sha256(request.token.as_bytes()), &db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Unable to load password reset token. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or_else(|| {
+            tracing::warn!("Unknown, expired or used password reset token");
+            StatusCode::BAD_REQUEST
+        })?;
+
+    LocalCredential::update_password(token.user_id, &hash_password(&request.new_password)?, &db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Unable to update password. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    revoke_sessions(token.user_id, &state, &db).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,16 @@
+use axum::{extract::Extension, http::header::CONTENT_TYPE, response::IntoResponse};
+
+use crate::{application::ApplicationState, driver::db, metrics::PoolStats};
+
+/// Exposes the metrics in the Prometheus text format.
+pub async fn metrics(
+    Extension(state): Extension<ApplicationState>,
+    Extension(db): Extension<db::DB>,
+) -> impl IntoResponse {
+    let pool = PoolStats::sample(&db);
+
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(&pool),
+    )
+}
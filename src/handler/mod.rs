@@ -1 +1,6 @@
+pub mod api_key;
 pub mod authentication;
+pub mod health;
+pub mod local_auth;
+pub mod metrics;
+pub mod well_known;
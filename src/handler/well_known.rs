@@ -0,0 +1,42 @@
+use axum::{extract::Extension, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+
+use crate::application::ApplicationState;
+
+/// Publishes the public keys for verifying JWTs issued by Aurelia.
+pub async fn jwks(Extension(state): Extension<ApplicationState>) -> impl IntoResponse {
+    state
+        .keys
+        .jwk_set(&state.configuration.application.auth)
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!("Unable to create JWK set. Error was: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Minimal OpenID Connect Discovery document, which is sufficient for
+/// standard JWT libraries to locate the JWK set.
+#[derive(Serialize, Debug)]
+pub struct OpenIdConfiguration {
+    pub issuer: String,
+    pub jwks_uri: String,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+}
+
+pub async fn openid_configuration(
+    Extension(state): Extension<ApplicationState>,
+) -> impl IntoResponse {
+    let http = &state.configuration.http;
+
+    Json(OpenIdConfiguration {
+        jwks_uri: format!("{}/.well-known/jwks.json", http.full_base_url()),
+        issuer: state.configuration.application.auth.issuer(http),
+        id_token_signing_alg_values_supported: vec![state
+            .configuration
+            .application
+            .auth
+            .jwt_algorithm
+            .clone()],
+    })
+}
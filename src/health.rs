@@ -0,0 +1,158 @@
+use std::{future::Future, time::Duration};
+
+use anyhow::{anyhow, Result};
+use futures::future::{join_all, FutureExt};
+use serde::Serialize;
+
+use crate::{application::ApplicationState, database_migrations::migrations, driver::db};
+
+/// Time a single readiness check may take, before it counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+}
+
+/// Outcome of a single readiness check, e.g. of an application defined one
+/// returned by `AppHandler::readiness_checks`. `/readyz` is public, so the
+/// error is only logged, never part of the response.
+#[derive(Serialize, Debug, Clone)]
+pub struct CheckResult {
+    #[serde(skip)]
+    pub name: String,
+    pub status: CheckStatus,
+    #[serde(skip)]
+    pub error: Option<String>,
+}
+
+impl CheckResult {
+    pub fn ok(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Ok,
+            error: None,
+        }
+    }
+
+    pub fn failed(name: &str, error: &str) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Failed,
+            error: Some(error.into()),
+        }
+    }
+
+    pub fn from_result(name: &str, result: Result<()>) -> Self {
+        match result {
+            Ok(()) => Self::ok(name),
+            Err(e) => Self::failed(name, &e.to_string()),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == CheckStatus::Ok
+    }
+}
+
+async fn check<F>(name: String, check: F) -> CheckResult
+where
+    F: Future<Output = Result<()>>,
+{
+    let result = tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("Timed out after {}s", CHECK_TIMEOUT.as_secs())));
+
+    if let Err(e) = &result {
+        tracing::warn!("Readiness check '{}' failed. Error was: {}", name, e);
+    }
+    CheckResult::from_result(&name, result)
+}
+
+async fn check_database(db: &db::DB) -> Result<()> {
+    sqlx::query("SELECT 1").execute(db).await?;
+    Ok(())
+}
+
+/// Aurelia's own migrations must be applied up to the latest embedded one.
+async fn check_migrations(db: &db::DB) -> Result<()> {
+    let expected = migrations::runner()
+        .get_migrations()
+        .iter()
+        .map(|m| m.version())
+        .max();
+    let (applied,): (Option<i32>,) =
+        sqlx::query_as("SELECT MAX(version) FROM aurelia_schema_history")
+            .fetch_one(db)
+            .await?;
+
+    if applied < expected {
+        return Err(anyhow!(
+            "Database schema is at version {:?}, expected {:?}",
+            applied,
+            expected
+        ));
+    }
+    Ok(())
+}
+
+/// The provider is usable, as long as its discovery metadata is available,
+/// which is possibly served stale from the cache.
+async fn check_oidc(state: &ApplicationState, provider_name: &str) -> Result<()> {
+    let provider = state
+        .configuration
+        .get_oidc_provider(provider_name)
+        .ok_or_else(|| anyhow!("Unknown OIDC provider"))?;
+    state.oidc_metadata.get(&provider).await?;
+    Ok(())
+}
+
+/// Runs the built-in checks for Postgres, the migration state and the OIDC
+/// providers concurrently with the checks of the `AppHandler`, which are
+/// reported as `app:<name>`.
+pub async fn readiness(state: &ApplicationState, db: &db::DB) -> Vec<CheckResult> {
+    let mut checks = vec![
+        check("database".into(), check_database(db)).boxed(),
+        check("migrations".into(), check_migrations(db)).boxed(),
+    ];
+    for provider_name in state.configuration.application.auth.oidc.keys() {
+        checks.push(
+            check(
+                format!("oidc:{}", provider_name),
+                check_oidc(state, provider_name),
+            )
+            .boxed(),
+        );
+    }
+
+    let custom = tokio::time::timeout(
+        CHECK_TIMEOUT,
+        state
+            .handlers
+            .readiness_checks(&state.configuration, db.clone()),
+    );
+
+    let (mut results, custom) = tokio::join!(join_all(checks), custom);
+    let mut custom = custom.unwrap_or_else(|_| {
+        vec![CheckResult::failed(
+            "readiness_checks",
+            &format!("Timed out after {}s", CHECK_TIMEOUT.as_secs()),
+        )]
+    });
+    // Namespaced, so they never replace a built-in check.
+    for result in custom.iter_mut() {
+        result.name = format!("app:{}", result.name);
+    }
+    for result in custom.iter().filter(|r| !r.is_ok()) {
+        tracing::warn!(
+            "Readiness check '{}' failed. Error was: {}",
+            result.name,
+            result.error.as_deref().unwrap_or_default()
+        );
+    }
+    results.extend(custom);
+
+    results
+}
@@ -0,0 +1,384 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    ec::{EcGroup, EcKey},
+    nid::Nid,
+    pkey::{Id, PKey, Public},
+    rsa::Rsa,
+    sha::sha256,
+};
+use serde::Serialize;
+
+use crate::configuration::Auth;
+
+/// Public JSON Web Key (RFC 7517) of a key used for signing JWTs.
+#[derive(Serialize, Clone, Debug)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(rename = "use")]
+    pub key_use: String,
+    pub alg: String,
+    pub kid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+/// Parses a PEM encoded public key (SubjectPublicKeyInfo or PKCS#1 RSA).
+fn public_key_from_pem(pem: &str) -> Result<PKey<Public>> {
+    Ok(PKey::public_key_from_pem(pem.as_bytes())
+        .or_else(|_| Rsa::public_key_from_pem_pkcs1(pem.as_bytes()).and_then(PKey::from_rsa))?)
+}
+
+/// The algorithm a key signs with is determined by its type. RSA keys use
+/// the configured `jwt_algorithm`, if it is an RSA algorithm.
+pub fn key_algorithm(pem: &str, configured: Algorithm) -> Result<Algorithm> {
+    let key = public_key_from_pem(pem)?;
+    Ok(match key.id() {
+        Id::RSA => match configured {
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => configured,
+            _ => Algorithm::RS256,
+        },
+        Id::EC => match key.ec_key()?.group().curve_name() {
+            Some(Nid::X9_62_PRIME256V1) => Algorithm::ES256,
+            Some(Nid::SECP384R1) => Algorithm::ES384,
+            curve => return Err(anyhow!("Unsupported elliptic curve: {:?}", curve)),
+        },
+        Id::ED25519 => Algorithm::EdDSA,
+        id => return Err(anyhow!("Unsupported public key type: {:?}", id)),
+    })
+}
+
+impl Jwk {
+    /// Creates the JWK of a PEM encoded public key. The `kid` is the
+    /// RFC 7638 thumbprint of the key, hence stable for the same key.
+    pub fn from_public_key_pem(pem: &str, alg: &str) -> Result<Self> {
+        let key = public_key_from_pem(pem)?;
+
+        match key.id() {
+            Id::RSA => Self::from_rsa(&key, alg),
+            Id::EC => Self::from_ec(&key, alg),
+            Id::ED25519 => Self::from_ed25519(&key, alg),
+            id => Err(anyhow!("Unsupported public key type: {:?}", id)),
+        }
+    }
+
+    fn from_rsa(key: &PKey<Public>, alg: &str) -> Result<Self> {
+        let rsa = key.rsa()?;
+        let n = base64_url(&rsa.n().to_vec());
+        let e = base64_url(&rsa.e().to_vec());
+
+        // Members in lexicographic order, see RFC 7638 section 3.
+        let kid = thumbprint(&format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, e, n));
+
+        Ok(Self {
+            kty: "RSA".into(),
+            key_use: "sig".into(),
+            alg: alg.into(),
+            kid,
+            n: Some(n),
+            e: Some(e),
+            crv: None,
+            x: None,
+            y: None,
+        })
+    }
+
+    fn from_ec(key: &PKey<Public>, alg: &str) -> Result<Self> {
+        let ec = key.ec_key()?;
+        let group = ec.group();
+        let crv = match group.curve_name() {
+            Some(Nid::X9_62_PRIME256V1) => "P-256",
+            Some(Nid::SECP384R1) => "P-384",
+            curve => return Err(anyhow!("Unsupported elliptic curve: {:?}", curve)),
+        };
+
+        let mut ctx = BigNumContext::new()?;
+        let mut x = BigNum::new()?;
+        let mut y = BigNum::new()?;
+        ec.public_key()
+            .affine_coordinates_gfp(group, &mut x, &mut y, &mut ctx)?;
+
+        // Coordinates have the full length of the curve's field size.
+        let len = group.degree().div_ceil(8) as usize;
+        let x = base64_url(&left_pad(x.to_vec(), len));
+        let y = base64_url(&left_pad(y.to_vec(), len));
+
+        let kid = thumbprint(&format!(
+            r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#,
+            crv, x, y
+        ));
+
+        Ok(Self {
+            kty: "EC".into(),
+            key_use: "sig".into(),
+            alg: alg.into(),
+            kid,
+            n: None,
+            e: None,
+            crv: Some(crv.into()),
+            x: Some(x),
+            y: Some(y),
+        })
+    }
+
+    fn from_ed25519(key: &PKey<Public>, alg: &str) -> Result<Self> {
+        // The raw key are the last 32 bytes of the SubjectPublicKeyInfo.
+        let der = key.public_key_to_der()?;
+        let x = base64_url(&der[der.len() - 32..]);
+
+        let kid = thumbprint(&format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, x));
+
+        Ok(Self {
+            kty: "OKP".into(),
+            key_use: "sig".into(),
+            alg: alg.into(),
+            kid,
+            n: None,
+            e: None,
+            crv: Some("Ed25519".into()),
+            x: Some(x),
+            y: None,
+        })
+    }
+}
+
+/// HMAC algorithms use the `jwt_secret`, all others a key pair.
+pub fn is_hmac(alg: Algorithm) -> bool {
+    matches!(alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512)
+}
+
+/// Creates the jsonwebtoken key for signing from a PEM encoded private key.
+pub fn encoding_key(alg: Algorithm, pem: &str) -> Result<EncodingKey> {
+    Ok(match alg {
+        Algorithm::ES256 | Algorithm::ES384 => EncodingKey::from_ec_pem(pem.as_bytes())?,
+        Algorithm::EdDSA => EncodingKey::from_ed_pem(pem.as_bytes())?,
+        _ => EncodingKey::from_rsa_pem(pem.as_bytes())?,
+    })
+}
+
+/// Creates the jsonwebtoken key for verifying from a PEM encoded public key.
+pub fn decoding_key(alg: Algorithm, pem: &str) -> Result<DecodingKey> {
+    Ok(match alg {
+        Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(pem.as_bytes())?,
+        Algorithm::EdDSA => DecodingKey::from_ed_pem(pem.as_bytes())?,
+        _ => DecodingKey::from_rsa_pem(pem.as_bytes())?,
+    })
+}
+
+/// Generates a new key pair for the given algorithm and returns the PEM
+/// encoded ``(private_key, public_key)``.
+pub fn generate_keypair(alg: Algorithm) -> Result<(String, String)> {
+    let (private_key, public_key) = match alg {
+        Algorithm::ES256 | Algorithm::ES384 => {
+            let nid = if alg == Algorithm::ES256 {
+                Nid::X9_62_PRIME256V1
+            } else {
+                Nid::SECP384R1
+            };
+            let group = EcGroup::from_curve_name(nid)?;
+            let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+            (key.private_key_to_pem_pkcs8()?, key.public_key_to_pem()?)
+        }
+        Algorithm::EdDSA => {
+            let key = PKey::generate_ed25519()?;
+            (key.private_key_to_pem_pkcs8()?, key.public_key_to_pem()?)
+        }
+        alg if is_hmac(alg) => {
+            return Err(anyhow!(
+                "{:?} uses the 'jwt_secret' instead of a key pair",
+                alg
+            ))
+        }
+        _ => {
+            let rsa = Rsa::generate(4096)?;
+            (rsa.private_key_to_pem()?, rsa.public_key_to_pem()?)
+        }
+    };
+
+    Ok((
+        String::from_utf8(private_key)?,
+        String::from_utf8(public_key)?,
+    ))
+}
+
+/// Resolved key of the configured JWT key set.
+#[derive(Clone)]
+pub struct Key {
+    pub kid: String,
+    pub private_key: Option<String>,
+    pub public_key: String,
+    pub active: bool,
+    /// Derived from the key type, see `key_algorithm`.
+    pub algorithm: Algorithm,
+    pub jwk: Jwk,
+    decoding_key: DecodingKey,
+}
+
+impl Key {
+    fn new(
+        kid: Option<String>,
+        private_key: Option<String>,
+        public_key: String,
+        active: bool,
+        configured: Algorithm,
+    ) -> Result<Self> {
+        let algorithm = key_algorithm(&public_key, configured)?;
+        let mut jwk = Jwk::from_public_key_pem(&public_key, &format!("{:?}", algorithm))?;
+        if let Some(kid) = kid {
+            jwk.kid = kid;
+        }
+
+        Ok(Self {
+            kid: jwk.kid.clone(),
+            decoding_key: decoding_key(algorithm, &public_key)?,
+            algorithm,
+            private_key,
+            public_key,
+            active,
+            jwk,
+        })
+    }
+
+    pub fn decoding_key(&self) -> &DecodingKey {
+        &self.decoding_key
+    }
+}
+
+/// The configured JWT keys, parsed once when the application state is built.
+#[derive(Clone)]
+pub struct KeySet {
+    keys: Arc<Vec<Key>>,
+    signing_key: Option<Arc<EncodingKey>>,
+}
+
+impl std::fmt::Debug for KeySet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.keys.iter().map(|k| &k.kid))
+            .finish()
+    }
+}
+
+impl KeySet {
+    /// Resolves all configured keys. The legacy `jwt_rsa_public_key` is part
+    /// of the key set (and signs, if no other key is active), unless it is
+    /// empty. Without `jwt_rsa_private_key` it only verifies.
+    pub fn new(auth: &Auth) -> Result<Self> {
+        let alg: Algorithm = auth.jwt_algorithm.parse()?;
+        let mut keys = auth
+            .jwt_keys
+            .iter()
+            .map(|k| {
+                Key::new(
+                    k.kid.clone(),
+                    k.private_key.clone(),
+                    k.public_key.clone(),
+                    k.active,
+                    alg,
+                )
+            })
+            .collect::<Result<Vec<Key>>>()?;
+
+        if keys.iter().filter(|k| k.active).count() > 1 {
+            return Err(anyhow!("Only one key of 'jwt_keys' may be active."));
+        }
+
+        if !auth.jwt_rsa_public_key.is_empty() {
+            let active = !keys.iter().any(|k| k.active);
+            let private_key = Some(auth.jwt_rsa_private_key.clone()).filter(|k| !k.is_empty());
+            keys.push(Key::new(
+                None,
+                private_key,
+                auth.jwt_rsa_public_key.clone(),
+                active,
+                alg,
+            )?);
+        }
+
+        let signing_key = match keys.iter().find(|k| k.active) {
+            Some(Key {
+                private_key: Some(pem),
+                algorithm,
+                ..
+            }) => Some(Arc::new(encoding_key(*algorithm, pem)?)),
+            _ => None,
+        };
+
+        Ok(Self {
+            keys: Arc::new(keys),
+            signing_key,
+        })
+    }
+
+    pub fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+
+    /// Returns the key, which signs new JWTs, with its parsed private key.
+    pub fn signing_key(&self) -> Result<(&Key, &EncodingKey)> {
+        match (self.keys.iter().find(|k| k.active), &self.signing_key) {
+            (Some(key), Some(encoding_key)) => Ok((key, encoding_key)),
+            _ => Err(anyhow!(
+                "No active JWT signing key with private key configured."
+            )),
+        }
+    }
+
+    /// Returns the key for verifying a JWT with the given `kid`. JWTs without
+    /// `kid` (issued before key rotation was supported) use the signing key.
+    pub fn verification_key(&self, kid: Option<&str>) -> Result<&Key> {
+        match kid {
+            Some(kid) => self.keys.iter().find(|k| k.kid == kid),
+            None => self.keys.iter().find(|k| k.active),
+        }
+        .ok_or_else(|| anyhow!("No JWT verification key found for kid: {:?}", kid))
+    }
+
+    /// Returns the set of public keys, which verify JWTs issued by Aurelia.
+    /// Symmetric keys are never published.
+    pub fn jwk_set(&self, auth: &Auth) -> Result<JwkSet> {
+        if is_hmac(auth.jwt_algorithm.parse()?) {
+            return Ok(JwkSet { keys: vec![] });
+        }
+
+        Ok(JwkSet {
+            keys: self.keys.iter().map(|k| k.jwk.clone()).collect(),
+        })
+    }
+}
+
+fn thumbprint(canonical_jwk: &str) -> String {
+    base64_url(&sha256(canonical_jwk.as_bytes()))
+}
+
+fn left_pad(mut data: Vec<u8>, len: usize) -> Vec<u8> {
+    while data.len() < len {
+        data.insert(0, 0);
+    }
+    data
+}
+
+fn base64_url(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
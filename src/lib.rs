@@ -1,12 +1,18 @@
 pub mod application;
 pub mod cli;
 pub mod configuration;
+pub mod crypto;
 pub mod database;
 mod database_migrations;
 pub mod driver;
 mod handler;
+pub mod health;
+pub mod keys;
+pub mod metrics;
 pub mod middleware;
+pub mod return_to;
 mod routes;
+mod server;
 pub mod telemetry;
 pub mod template;
 pub mod testing;
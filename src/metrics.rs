@@ -0,0 +1,238 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::driver::db;
+
+/// Upper bounds of the request latency buckets in seconds, the Prometheus
+/// client defaults.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Cumulative counts per bucket of `LATENCY_BUCKETS`.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    /// Latencies by method, matched route and status.
+    http_requests: BTreeMap<(String, String, u16), Histogram>,
+    /// Count by failure reason, `None` for successes.
+    jwt_authentications: BTreeMap<Option<&'static str>, u64>,
+    /// Count by provider and result.
+    oidc_logins: BTreeMap<(String, String), u64>,
+}
+
+/// Connection pool state at the time of a scrape.
+#[derive(Debug, Clone)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: usize,
+}
+
+impl PoolStats {
+    /// Samples the pool without acquiring a connection, so scrapes never
+    /// compete with the application for one.
+    pub fn sample(db: &db::DB) -> Self {
+        Self {
+            size: db.size(),
+            idle: db.num_idle(),
+        }
+    }
+}
+
+/// Prometheus metrics of the application. Nothing is recorded, until
+/// `Application::with_metrics` enables them.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    enabled: Arc<AtomicBool>,
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Metrics {
+    pub(crate) fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    fn record<F: FnOnce(&mut Registry)>(&self, f: F) {
+        if self.is_enabled() {
+            f(&mut self.registry.lock().unwrap());
+        }
+    }
+
+    pub fn record_http_request(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        self.record(|r| {
+            r.http_requests
+                .entry((method.into(), route.into(), status))
+                .or_default()
+                .observe(latency.as_secs_f64())
+        });
+    }
+
+    /// Records a JWT or API key authentication, failed with the given
+    /// reason, if any.
+    pub fn record_jwt_authentication(&self, failure_reason: Option<&'static str>) {
+        self.record(|r| *r.jwt_authentications.entry(failure_reason).or_default() += 1);
+    }
+
+    /// Records an OIDC login with `success` or the error code as result.
+    pub fn record_oidc_login(&self, provider_name: &str, result: &str) {
+        self.record(|r| {
+            *r.oidc_logins
+                .entry((provider_name.into(), result.into()))
+                .or_default() += 1
+        });
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self, pool: &PoolStats) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "aurelia_http_requests_total",
+            "counter",
+            "Number of handled HTTP requests.",
+        );
+        for ((method, route, status), h) in registry.http_requests.iter() {
+            let labels = labels(&[
+                ("method", method),
+                ("route", route),
+                ("status", &status.to_string()),
+            ]);
+            let _ = writeln!(out, "aurelia_http_requests_total{{{}}} {}", labels, h.count);
+        }
+
+        header(
+            &mut out,
+            "aurelia_http_request_duration_seconds",
+            "histogram",
+            "Latency of handled HTTP requests.",
+        );
+        for ((method, route, status), h) in registry.http_requests.iter() {
+            let labels = labels(&[
+                ("method", method),
+                ("route", route),
+                ("status", &status.to_string()),
+            ]);
+            for (count, bound) in h.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "aurelia_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "aurelia_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, h.count
+            );
+            let _ = writeln!(
+                out,
+                "aurelia_http_request_duration_seconds_sum{{{}}} {}",
+                labels, h.sum
+            );
+            let _ = writeln!(
+                out,
+                "aurelia_http_request_duration_seconds_count{{{}}} {}",
+                labels, h.count
+            );
+        }
+
+        header(
+            &mut out,
+            "aurelia_jwt_authentications_total",
+            "counter",
+            "Number of JWT and API key authentications by result and failure reason.",
+        );
+        for (reason, count) in registry.jwt_authentications.iter() {
+            let labels = match reason {
+                Some(reason) => labels(&[("result", "failure"), ("reason", reason)]),
+                None => labels(&[("result", "success")]),
+            };
+            let _ = writeln!(
+                out,
+                "aurelia_jwt_authentications_total{{{}}} {}",
+                labels, count
+            );
+        }
+
+        header(
+            &mut out,
+            "aurelia_oidc_logins_total",
+            "counter",
+            "Number of OIDC logins by provider and result.",
+        );
+        for ((provider_name, result), count) in registry.oidc_logins.iter() {
+            let labels = labels(&[("provider", provider_name), ("result", result)]);
+            let _ = writeln!(out, "aurelia_oidc_logins_total{{{}}} {}", labels, count);
+        }
+
+        gauge(
+            &mut out,
+            "aurelia_db_pool_connections",
+            "Number of open database connections.",
+            pool.size,
+        );
+        gauge(
+            &mut out,
+            "aurelia_db_pool_idle_connections",
+            "Number of idle database connections.",
+            pool.idle,
+        );
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn gauge<V: std::fmt::Display>(out: &mut String, name: &str, help: &str, value: V) {
+    header(out, name, "gauge", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
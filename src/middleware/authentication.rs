@@ -1,23 +1,33 @@
 use std::{
+    fmt,
     str::FromStr,
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{anyhow, Result};
 use axum::{
-    http::Request,
+    extract::OriginalUri,
+    http::{Extensions, HeaderMap, Request, Uri},
     middleware::Next,
     response::{IntoResponse, Redirect},
 };
 use headers::{Cookie, HeaderMapExt};
 use hyper::StatusCode;
 use jsonwebtoken::{
-    decode, encode, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
+    decode, decode_header, encode, errors::ErrorKind, Algorithm, DecodingKey, EncodingKey, Header,
+    TokenData, Validation,
 };
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::configuration::{Application, Configuration};
+use crate::{
+    application::ApplicationState,
+    configuration::{Application, Configuration},
+    database::entity::api_key::ApiKey,
+    driver::db,
+    keys::{is_hmac, KeySet},
+    return_to,
+};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct JwtClaims {
@@ -28,8 +38,55 @@ pub struct JwtClaims {
     pub family_name: String,
     pub picture: Option<String>,
     pub exp: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    /// `client_role` of the OIDC provider the user logged in with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_role: Option<String>,
+    /// Id of the stored provider refresh token, used by `/auth/refresh`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// Roles of the user, usually filled in by `AppHandler::on_login_callback`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    /// Permissions of the user, e.g. `recipe:write`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permissions: Vec<String>,
+    /// Id of the API key, if the request was authenticated with one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// Application defined claims, e.g. a tenant id, set with `set_extra`.
+    /// Serialized as top level claims of the JWT.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
+/// Claims of `JwtClaims`, which can't be used as extra claims.
+pub const RESERVED_CLAIMS: &[&str] = &[
+    "sub",
+    "iss",
+    "email",
+    "given_name",
+    "family_name",
+    "picture",
+    "exp",
+    "aud",
+    "iat",
+    "nbf",
+    "jti",
+    "client_role",
+    "sid",
+    "roles",
+    "permissions",
+    "api_key",
+];
+
 impl JwtClaims {
     pub fn new(
         sub: String,
@@ -47,13 +104,49 @@ impl JwtClaims {
             given_name,
             family_name,
             picture,
-            // TODO: Use chrono here, if useful
-            exp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as usize
-                + exp_offset,
+            exp: expiration(exp_offset),
+            aud: None,
+            iat: None,
+            nbf: None,
+            jti: None,
+            client_role: None,
+            sid: None,
+            roles: vec![],
+            permissions: vec![],
+            api_key: None,
+            extra: serde_json::Map::new(),
+        }
+    }
+
+    /// Extends the expiration to ``exp_offset`` seconds from now.
+    pub fn renew(&mut self, exp_offset: usize) {
+        self.exp = expiration(exp_offset);
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
+
+    /// Sets an application defined claim, which fails for the claims
+    /// `JwtClaims` already has (see `RESERVED_CLAIMS`).
+    pub fn set_extra<T: Serialize>(&mut self, name: &str, value: T) -> Result<()> {
+        if RESERVED_CLAIMS.contains(&name) {
+            return Err(anyhow!("'{}' is a reserved claim.", name));
         }
+        self.extra
+            .insert(name.to_string(), serde_json::to_value(value)?);
+        Ok(())
+    }
+
+    /// Returns an application defined claim, if it is set and of type `T`.
+    pub fn extra<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        self.extra
+            .get(name)
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
     }
 
     pub fn full_name(&self) -> String {
@@ -70,26 +163,146 @@ impl JwtClaims {
     }
 }
 
+// TODO: Use chrono here, if useful
+fn now() -> usize {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as usize
+}
+
+fn expiration(exp_offset: usize) -> usize {
+    now() + exp_offset
+}
+
+/// Reasons of failed authentications, which aren't errors of decoding the
+/// JWT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AuthFailure {
+    MissingCredentials,
+    InvalidApiKey,
+    Revoked,
+    Rejected,
+}
+
+impl fmt::Display for AuthFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AuthFailure::MissingCredentials => "No JWT or API key found.",
+            AuthFailure::InvalidApiKey => "Unknown, revoked or expired API key.",
+            AuthFailure::Revoked => "JWT is revoked.",
+            AuthFailure::Rejected => "Rejected by the application.",
+        })
+    }
+}
+
+impl std::error::Error for AuthFailure {}
+
+impl AuthFailure {
+    /// Reason of the failed authentication as metrics label, `None` for
+    /// anonymous requests.
+    fn reason(e: &anyhow::Error) -> Option<&'static str> {
+        if let Some(failure) = e.downcast_ref::<AuthFailure>() {
+            return match failure {
+                AuthFailure::MissingCredentials => None,
+                AuthFailure::InvalidApiKey => Some("invalid_api_key"),
+                AuthFailure::Revoked => Some("revoked"),
+                AuthFailure::Rejected => Some("rejected"),
+            };
+        }
+
+        match e
+            .downcast_ref::<jsonwebtoken::errors::Error>()
+            .map(|e| e.kind())
+        {
+            Some(ErrorKind::ExpiredSignature) => Some("expired"),
+            Some(ErrorKind::InvalidSignature) => Some("invalid_signature"),
+            Some(ErrorKind::InvalidIssuer | ErrorKind::InvalidAudience) => Some("invalid_claims"),
+            _ => Some("invalid_token"),
+        }
+    }
+}
+
+/// Result of authenticating a request, which is stored in the request
+/// extensions, so the extractors don't authenticate a request twice.
+#[derive(Clone, Debug)]
+pub(crate) struct Authentication {
+    pub claims: Option<JwtClaims>,
+    /// All claims of the JWT, incl. the ones unknown to `JwtClaims`.
+    pub raw_claims: Option<serde_json::Value>,
+}
+
+impl Authentication {
+    /// Authenticates the request, see `authenticate`, and logs the outcome.
+    pub(crate) async fn of(
+        headers: &HeaderMap,
+        extensions: &Extensions,
+        app_config: &Application,
+    ) -> Self {
+        let result = authenticate(headers, extensions, app_config).await;
+        if let Some(state) = extensions.get::<ApplicationState>() {
+            match &result {
+                Ok(_) => state.metrics.record_jwt_authentication(None),
+                Err(e) => {
+                    if let Some(reason) = AuthFailure::reason(e) {
+                        state.metrics.record_jwt_authentication(Some(reason));
+                    }
+                }
+            }
+        }
+
+        match result {
+            Ok((claims, raw_claims)) => {
+                tracing::info!("Successfully authenticated JWT with claims: {:?}", claims);
+                Self {
+                    claims: Some(claims),
+                    raw_claims: Some(raw_claims),
+                }
+            }
+            Err(e) => {
+                tracing::warn!("JWT authentication failed: {:#}", e);
+                Self {
+                    claims: None,
+                    raw_claims: None,
+                }
+            }
+        }
+    }
+
+    /// Authenticates the request, unless `jwt_auth_middleware`, a route layer
+    /// or an extractor already did, and keeps the result in the extensions.
+    pub(crate) async fn resolve(headers: &HeaderMap, extensions: &mut Extensions) -> Self {
+        if let Some(authentication) = extensions.get::<Authentication>() {
+            return authentication.clone();
+        }
+
+        let authentication = match extensions.get::<ApplicationState>().cloned() {
+            Some(state) => Self::of(headers, extensions, &state.configuration.application).await,
+            None => {
+                tracing::error!("No ApplicationState available for authenticating the request.");
+                Self {
+                    claims: None,
+                    raw_claims: None,
+                }
+            }
+        };
+
+        extensions.insert(authentication.clone());
+        authentication
+    }
+}
+
+/// Authenticates the request and provides the claims as
+/// `Extension<Option<JwtClaims>>` (`None` for anonymous requests).
 pub async fn jwt_auth_middleware<B>(
     mut req: Request<B>,
     next: Next<B>,
     config: Application,
 ) -> impl IntoResponse {
-    match jwt_authentication(&req, &config).await {
-        Ok(claims) => {
-            tracing::info!("Successfully authenticated JWT with claims: {:?}", claims);
-            req.extensions_mut()
-                .get_mut::<Option<JwtClaims>>()
-                .unwrap_or(&mut None)
-                .replace(claims);
-        }
-        Err(e) => {
-            req.extensions_mut()
-                .get::<Option<JwtClaims>>()
-                .replace(&None);
-            tracing::warn!("JWT authentication failed: {}", e);
-        }
-    }
+    let authentication = Authentication::of(req.headers(), req.extensions(), &config).await;
+
+    req.extensions_mut().insert(authentication.claims.clone());
+    req.extensions_mut().insert(authentication);
 
     Ok::<_, StatusCode>(next.run(req).await)
 }
@@ -101,24 +314,95 @@ pub async fn require_jwt_authentication<B>(
 ) -> impl IntoResponse {
     let claims = req.extensions().get::<Option<JwtClaims>>().unwrap_or(&None);
     if claims.is_none() {
-        return Err(Redirect::to(config.auth.login_path.as_str()));
+        let uri = req
+            .extensions()
+            .get::<OriginalUri>()
+            .map(|OriginalUri(uri)| uri)
+            .unwrap_or_else(|| req.uri());
+        return Err(login_redirect(uri, &config));
     }
 
     Ok::<_, Redirect>(next.run(req).await)
 }
 
-/// Takes an ``axum::Request`` and tries to extract and decode the
-/// JWT from a configurable HTTP header.
-pub(crate) async fn jwt_authentication<ReqBody>(
-    req: &Request<ReqBody>,
+/// Redirects to the `login_path`, which sends the user back to the
+/// requested page after the login.
+pub(crate) fn login_redirect(uri: &Uri, config: &Application) -> Redirect {
+    let login_path = match uri.path_and_query() {
+        Some(target) if return_to::is_allowed(&config.auth, target.as_str()) => {
+            return_to::append(&config.auth.login_path, target.as_str())
+        }
+        _ => config.auth.login_path.clone(),
+    };
+
+    Redirect::to(&login_path)
+}
+
+/// Authenticates with an API key or a JWT and returns the claims and all raw
+/// claims, if `AppHandler::on_request_authenticated` accepts them. The
+/// database (for API keys) and the `ApplicationState` (incl. the revoked
+/// JWTs) are taken from the request extensions.
+async fn authenticate(
+    headers: &HeaderMap,
+    extensions: &Extensions,
     app_config: &Application,
-) -> Result<JwtClaims> {
+) -> Result<(JwtClaims, serde_json::Value)> {
+    let (claims, raw_claims) = verify_credentials(headers, extensions, app_config).await?;
+
+    if let (Some(state), Some(db)) = (
+        extensions.get::<ApplicationState>(),
+        extensions.get::<db::DB>(),
+    ) {
+        state
+            .handlers
+            .on_request_authenticated(&claims, &state.configuration, db.clone())
+            .await
+            .map_err(|e| e.context(AuthFailure::Rejected))?;
+    }
+
+    Ok((claims, raw_claims))
+}
+
+async fn verify_credentials(
+    headers: &HeaderMap,
+    extensions: &Extensions,
+    app_config: &Application,
+) -> Result<(JwtClaims, serde_json::Value)> {
+    if let Some(key) = api_key_from_headers(headers, app_config) {
+        let db = extensions
+            .get::<db::DB>()
+            .ok_or_else(|| anyhow!("No database available for API key authentication."))?;
+        let claims = api_key_authentication(&key, app_config, db).await?;
+        let raw_claims = serde_json::to_value(&claims)?;
+        return Ok((claims, raw_claims));
+    }
+
+    let token = jwt_from_headers(headers, app_config)
+        .map_err(|e| e.context(AuthFailure::MissingCredentials))?;
+
+    let state = extensions
+        .get::<ApplicationState>()
+        .ok_or_else(|| anyhow!("No ApplicationState available for verifying the JWT."))?;
+    let raw_claims = decode_raw_token(&token, &state.configuration, &state.keys, true)?.claims;
+    let claims: JwtClaims = serde_json::from_value(raw_claims.clone())?;
+    tracing::debug!("JWT decoded successfully. Claims: {:?}", &claims);
+
+    if state.revocations.is_revoked(&claims).await {
+        return Err(AuthFailure::Revoked.into());
+    }
+
+    Ok((claims, raw_claims))
+}
+
+/// Extracts the raw JWT from the configurable HTTP header (as Bearer token)
+/// or, if that fails, from the configurable cookie.
+pub(crate) fn jwt_from_headers(headers: &HeaderMap, app_config: &Application) -> Result<String> {
     tracing::debug!(
         "Trying to authenticate JWT. Reading from HTTP header: {}",
         &app_config.auth.jwt_header_name
     );
 
-    let auth_header_result = match req.headers().get(&app_config.auth.jwt_header_name) {
+    let auth_header_result = match headers.get(&app_config.auth.jwt_header_name) {
         Some(value) => match value
             .to_str()
             .unwrap_or("")
@@ -133,10 +417,7 @@ pub(crate) async fn jwt_authentication<ReqBody>(
                     &app_config.auth.jwt_header_name
                 );
 
-                let token = verify_token(token, app_config).await?;
-                tracing::debug!("JWT decoded successfully. Claims: {:?}", &token.claims);
-
-                Ok(token.claims)
+                Ok(token.to_string())
             }
             any => Err(anyhow!(
                 "Authentication via '{}' header failed. Unsupported header format: '{}'",
@@ -156,15 +437,12 @@ pub(crate) async fn jwt_authentication<ReqBody>(
         tracing::debug!("{}", auth_header_result.as_ref().unwrap_err().to_string());
     }
 
-    match req.headers().typed_try_get::<Cookie>() {
+    match headers.typed_try_get::<Cookie>() {
         Ok(Some(cookie)) => {
             if let Some(token) = cookie.get(&app_config.auth.jwt_cookie_name) {
                 tracing::debug!("Found cookie id token header. Trying to decode JWT.");
 
-                let token = verify_token(token, app_config).await?;
-                tracing::debug!("JWT decoded successfully. Claims: {:?}", &token.claims);
-
-                Ok(token.claims)
+                Ok(token.to_string())
             } else {
                 Err(anyhow!(
                     "No id token cookie with name '{}' found.",
@@ -177,40 +455,169 @@ pub(crate) async fn jwt_authentication<ReqBody>(
     }
 }
 
-async fn verify_token(token: &str, app_config: &Application) -> Result<TokenData<JwtClaims>> {
-    let token = decode::<JwtClaims>(
-        token,
-        &DecodingKey::from_rsa_pem(app_config.auth.jwt_rsa_public_key.as_bytes())?,
-        &Validation::new(Algorithm::from_str(&app_config.auth.jwt_algorithm)?),
-    )?;
+/// Extracts an API key from the configurable API key header or from the
+/// JWT header as Bearer token with the `api_key_prefix`.
+fn api_key_from_headers(headers: &HeaderMap, app_config: &Application) -> Option<String> {
+    if let Some(key) = headers
+        .get(&app_config.auth.api_key_header_name)
+        .and_then(|v| v.to_str().ok())
+    {
+        return Some(key.trim().to_string());
+    }
+
+    let prefix = format!("{}_", app_config.auth.api_key_prefix);
+    headers
+        .get(&app_config.auth.jwt_header_name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().strip_prefix("Bearer "))
+        .map(|token| token.trim())
+        .filter(|token| token.starts_with(&prefix))
+        .map(|token| token.to_string())
+}
+
+/// Looks up a valid API key and returns claims for its subject, with the
+/// scopes of the key as permissions.
+async fn api_key_authentication(
+    key: &str,
+    app_config: &Application,
+    db: &db::DB,
+) -> Result<JwtClaims> {
+    let api_key = ApiKey::get_valid_by_hash(&ApiKey::hash(key), db)
+        .await?
+        .ok_or(AuthFailure::InvalidApiKey)?;
+
+    if let Err(e) = ApiKey::touch(api_key.id, db).await {
+        tracing::error!("Unable to track usage of API key {}: {}", api_key.id, e);
+    }
+
+    let mut claims = JwtClaims::new(
+        api_key.subject,
+        "api_key".into(),
+        "".into(),
+        "".into(),
+        "".into(),
+        None,
+        app_config.auth.jwt_expiration_offset_seconds,
+    );
+    if let Some(expires_at) = api_key.expires_at {
+        claims.exp = expires_at.timestamp() as usize;
+    }
+    claims.permissions = api_key.scopes;
+    claims.api_key = Some(api_key.id.to_string());
+
+    Ok(claims)
+}
+
+/// Verifies the signature of a JWT, which may already be expired. Only
+/// meant for exchanging an expired JWT against a fresh one.
+pub(crate) async fn verify_expired_token(
+    token: &str,
+    cfg: &Configuration,
+    keys: &KeySet,
+) -> Result<TokenData<JwtClaims>> {
+    decode_token(token, cfg, keys, false)
+}
+
+fn decode_token(
+    token: &str,
+    cfg: &Configuration,
+    keys: &KeySet,
+    validate_exp: bool,
+) -> Result<TokenData<JwtClaims>> {
+    let token = decode_raw_token(token, cfg, keys, validate_exp)?;
+
+    Ok(TokenData {
+        header: token.header,
+        claims: serde_json::from_value(token.claims)?,
+    })
+}
+
+/// Verifies a JWT and returns all of its claims.
+fn decode_raw_token(
+    token: &str,
+    cfg: &Configuration,
+    keys: &KeySet,
+    validate_exp: bool,
+) -> Result<TokenData<serde_json::Value>> {
+    let auth = &cfg.application.auth;
+    let header = decode_header(token)?;
+
+    // JWTs signed with a key of the key set carry its `kid`, the algorithm
+    // is the one of the key, never the one claimed by the JWT.
+    let secret;
+    let (alg, key) = match Algorithm::from_str(&auth.jwt_algorithm)? {
+        alg if is_hmac(alg) && header.kid.is_none() => {
+            secret = DecodingKey::from_secret(auth.jwt_secret.as_ref());
+            (alg, &secret)
+        }
+        _ => {
+            let key = keys.verification_key(header.kid.as_deref())?;
+            (key.algorithm, key.decoding_key())
+        }
+    };
+
+    let mut validation = Validation::new(alg);
+    validation.validate_exp = validate_exp;
+    validation.validate_nbf = true;
+    validation.leeway = auth.jwt_leeway_seconds;
+    validation.set_issuer(&[auth.issuer(&cfg.http)]);
+    validation.required_spec_claims.insert("iss".into());
+    if let Some(aud) = &auth.jwt_audience {
+        validation.set_audience(&[aud]);
+        validation.required_spec_claims.insert("aud".into());
+    }
+
+    let token = decode::<serde_json::Value>(token, key, &validation)?;
+
+    let iat = token.claims.get("iat").and_then(serde_json::Value::as_u64);
+    if matches!(iat, Some(iat) if iat as usize > now() + auth.jwt_leeway_seconds as usize) {
+        return Err(anyhow!("JWT is issued in the future."));
+    }
 
     Ok(token)
 }
 
+/// Signs the claims. The registered claims `iss`, `aud` (if configured),
+/// `iat`, `nbf` and `jti` are set on every issuance, e.g. also on refresh.
+/// `force_key_pair` signs with the active key (in its algorithm) or with the
+/// `jwt_secret` (HS256) instead of the configured `jwt_algorithm`.
 pub(crate) fn create_jwt_from_claims(
     cfg: &Configuration,
-    claims: JwtClaims,
-    force_rsa: Option<bool>,
+    keys: &KeySet,
+    mut claims: JwtClaims,
+    force_key_pair: Option<bool>,
 ) -> Result<String> {
-    let rsa = if let Some(force) = force_rsa {
-        force
-    } else {
-        cfg.application.auth.jwt_algorithm == "RS256"
-    };
+    let auth = &cfg.application.auth;
+    claims.iss = auth.issuer(&cfg.http);
+    claims.aud = auth.jwt_audience.clone();
+    claims.iat = Some(now());
+    claims.nbf = claims.iat;
+    claims.jti = Some(Uuid::new_v4().to_string());
+    // Extra claims inserted directly into the map must not duplicate claims.
+    claims
+        .extra
+        .retain(|name, _| !RESERVED_CLAIMS.contains(&name.as_str()));
 
-    if rsa {
-        let key = EncodingKey::from_rsa_pem(cfg.application.auth.jwt_rsa_private_key.as_bytes())?;
-        encode(&Header::new(Algorithm::RS256), &claims, &key).map_err(Into::into)
-    } else {
-        let key = EncodingKey::from_secret(cfg.application.auth.jwt_secret.as_ref());
-        encode(&Header::new(Algorithm::HS256), &claims, &key).map_err(Into::into)
+    let hmac = match force_key_pair {
+        Some(true) => None,
+        Some(false) => Some(Algorithm::HS256),
+        None => Some(Algorithm::from_str(&auth.jwt_algorithm)?).filter(|alg| is_hmac(*alg)),
+    };
+    if let Some(alg) = hmac {
+        let key = EncodingKey::from_secret(auth.jwt_secret.as_ref());
+        return encode(&Header::new(alg), &claims, &key).map_err(Into::into);
     }
+
+    let (signing_key, key) = keys.signing_key()?;
+    let mut header = Header::new(signing_key.algorithm);
+    header.kid = Some(signing_key.kid.clone());
+    encode(&header, &claims, key).map_err(Into::into)
 }
 
 pub async fn create_jwt(
     cfg: &Configuration,
     user_id: Option<Uuid>,
-    force_rsa: Option<bool>,
+    force_key_pair: Option<bool>,
 ) -> Result<String> {
     let sub = user_id.unwrap_or_else(Uuid::new_v4).to_string();
 
@@ -224,5 +631,10 @@ pub async fn create_jwt(
         cfg.application.auth.jwt_expiration_offset_seconds,
     );
 
-    create_jwt_from_claims(cfg, claims, force_rsa)
+    create_jwt_from_claims(
+        cfg,
+        &KeySet::new(&cfg.application.auth)?,
+        claims,
+        force_key_pair,
+    )
 }
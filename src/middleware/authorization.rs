@@ -0,0 +1,114 @@
+use axum::{
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use hyper::StatusCode;
+use serde::Serialize;
+
+use crate::middleware::authentication::{Authentication, JwtClaims};
+
+/// Body of a rejected request, e.g. `{"error": "forbidden", "message":
+/// "Missing role 'admin'"}`.
+#[derive(Serialize, Debug)]
+pub struct AuthorizationError {
+    pub error: &'static str,
+    pub message: String,
+}
+
+impl AuthorizationError {
+    pub fn unauthorized() -> Self {
+        Self {
+            error: "unauthorized",
+            message: "Authentication required".into(),
+        }
+    }
+
+    pub fn missing_role(role: &str) -> Self {
+        Self {
+            error: "forbidden",
+            message: format!("Missing role '{}'", role),
+        }
+    }
+
+    pub fn missing_permission(permission: &str) -> Self {
+        Self {
+            error: "forbidden",
+            message: format!("Missing permission '{}'", permission),
+        }
+    }
+}
+
+impl IntoResponse for AuthorizationError {
+    fn into_response(self) -> Response {
+        let status = if self.error == "unauthorized" {
+            StatusCode::UNAUTHORIZED
+        } else {
+            StatusCode::FORBIDDEN
+        };
+
+        (status, Json(self)).into_response()
+    }
+}
+
+/// Checks the claims of the request with the given predicate. The request is
+/// authenticated like by the extractors, so API keys and cookies work without
+/// `jwt_auth_middleware` as well.
+async fn authorize<B>(
+    req: Request<B>,
+    check: impl FnOnce(&JwtClaims) -> Result<(), AuthorizationError>,
+) -> Result<Request<B>, AuthorizationError> {
+    let (mut parts, body) = req.into_parts();
+    let authentication = Authentication::resolve(&parts.headers, &mut parts.extensions).await;
+    match &authentication.claims {
+        Some(claims) => check(claims)?,
+        None => return Err(AuthorizationError::unauthorized()),
+    }
+
+    Ok(Request::from_parts(parts, body))
+}
+
+/// Route layer, which only lets users with the given role pass, e.g.
+/// `middleware::from_fn(|req, next| require_role(req, next, "admin"))`.
+/// Anonymous requests get a 401, users without the role a 403.
+pub async fn require_role<B, R: AsRef<str>>(
+    req: Request<B>,
+    next: Next<B>,
+    role: R,
+) -> Result<Response, AuthorizationError> {
+    let role = role.as_ref();
+    let req = authorize(req, |claims| {
+        if claims.has_role(role) {
+            Ok(())
+        } else {
+            tracing::warn!("User {} is missing role '{}'", claims.sub, role);
+            Err(AuthorizationError::missing_role(role))
+        }
+    })
+    .await?;
+
+    Ok(next.run(req).await)
+}
+
+/// Route layer, which only lets users with the given permission pass, e.g.
+/// `middleware::from_fn(|req, next| require_permission(req, next, "recipe:write"))`.
+/// Anonymous requests get a 401, users without the permission a 403.
+pub async fn require_permission<B, P: AsRef<str>>(
+    req: Request<B>,
+    next: Next<B>,
+    permission: P,
+) -> Result<Response, AuthorizationError> {
+    let permission = permission.as_ref();
+    let req = authorize(req, |claims| {
+        if claims.has_permission(permission) {
+            Ok(())
+        } else {
+            tracing::warn!("User {} is missing permission '{}'", claims.sub, permission);
+            Err(AuthorizationError::missing_permission(permission))
+        }
+    })
+    .await?;
+
+    Ok(next.run(req).await)
+}
@@ -0,0 +1,126 @@
+use std::convert::Infallible;
+
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequest, OriginalUri, RequestParts},
+    http::{header::ACCEPT, Method},
+    response::{IntoResponse, Redirect, Response},
+};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    application::ApplicationState,
+    middleware::{
+        authentication::{login_redirect, Authentication, JwtClaims},
+        authorization::AuthorizationError,
+    },
+};
+
+/// Authenticated user, rejects anonymous requests. Browsers (`GET` requests
+/// accepting `text/html`) are redirected to the `login_path`, all other
+/// requests get a 401.
+#[derive(Clone, Debug)]
+pub struct AuthUser(pub JwtClaims);
+
+/// The user, if the request is authenticated. Never rejects.
+#[derive(Clone, Debug)]
+pub struct MaybeUser(pub Option<JwtClaims>);
+
+/// All claims of the JWT deserialized into `T`, e.g. to access claims, which
+/// `JwtClaims` doesn't know. Rejects like `AuthUser`.
+#[derive(Clone, Debug)]
+pub struct Claims<T>(pub T);
+
+pub enum AuthRejection {
+    Unauthorized,
+    LoginRedirect(Redirect),
+}
+
+impl IntoResponse for AuthRejection {
+    fn into_response(self) -> Response {
+        match self {
+            AuthRejection::Unauthorized => AuthorizationError::unauthorized().into_response(),
+            AuthRejection::LoginRedirect(redirect) => redirect.into_response(),
+        }
+    }
+}
+
+/// Authenticates the request, unless `jwt_auth_middleware` or another
+/// extractor already did.
+async fn authentication<B: Send>(req: &mut RequestParts<B>) -> Authentication {
+    if let Some(authentication) = req.extensions().get::<Authentication>() {
+        return authentication.clone();
+    }
+
+    let headers = req.headers().clone();
+    Authentication::resolve(&headers, req.extensions_mut()).await
+}
+
+fn rejection<B>(req: &RequestParts<B>) -> AuthRejection {
+    let is_browser = req.method() == Method::GET
+        && req
+            .headers()
+            .get(ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("text/html"));
+
+    match req.extensions().get::<ApplicationState>() {
+        Some(state) if is_browser => {
+            let uri = req
+                .extensions()
+                .get::<OriginalUri>()
+                .map(|OriginalUri(uri)| uri)
+                .unwrap_or_else(|| req.uri());
+            AuthRejection::LoginRedirect(login_redirect(uri, &state.configuration.application))
+        }
+        _ => AuthRejection::Unauthorized,
+    }
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for AuthUser
+where
+    B: Send,
+{
+    type Rejection = AuthRejection;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        match authentication(req).await.claims {
+            Some(claims) => Ok(AuthUser(claims)),
+            None => Err(rejection(req)),
+        }
+    }
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for MaybeUser
+where
+    B: Send,
+{
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(MaybeUser(authentication(req).await.claims))
+    }
+}
+
+#[async_trait]
+impl<B, T> FromRequest<B> for Claims<T>
+where
+    B: Send,
+    T: DeserializeOwned,
+{
+    type Rejection = AuthRejection;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let raw_claims = authentication(req)
+            .await
+            .raw_claims
+            .ok_or_else(|| rejection(req))?;
+
+        serde_json::from_value(raw_claims).map(Claims).map_err(|e| {
+            tracing::warn!("JWT claims don't match the expected claims: {}", e);
+            AuthRejection::Unauthorized
+        })
+    }
+}
@@ -0,0 +1,31 @@
+use std::time::Instant;
+
+use axum::{extract::MatchedPath, http::Request, middleware::Next, response::IntoResponse};
+
+use crate::metrics::Metrics;
+
+/// Records count and latency of the request by its matched route, so path
+/// parameters don't create a time series per value.
+pub async fn track_metrics<B>(
+    req: Request<B>,
+    next: Next<B>,
+    metrics: Metrics,
+) -> impl IntoResponse {
+    let start = Instant::now();
+    let method = req.method().clone();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".into());
+
+    let response = next.run(req).await;
+
+    metrics.record_http_request(
+        method.as_str(),
+        &route,
+        response.status().as_u16(),
+        start.elapsed(),
+    );
+    response
+}
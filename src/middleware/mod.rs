@@ -1,2 +1,5 @@
 pub mod authentication;
+pub mod authorization;
+pub mod extract;
+pub mod metrics;
 pub mod request_context;
@@ -0,0 +1,82 @@
+use openidconnect::url::{form_urlencoded, Url};
+
+use crate::configuration::Auth;
+
+/// Base for resolving relative targets, never part of a redirect.
+const RELATIVE_BASE: &str = "http://return-to.invalid";
+
+/// Checks a `return_to` target against `return_to_allowlist`, so the login
+/// flow can't be abused as an open redirect.
+///
+/// Relative targets (`/some/page?x=1`) match allowlist entries, which are
+/// paths. Absolute targets match entries, which are absolute URLs with the
+/// same scheme, host and port. In both cases the path of the entry has to be
+/// a prefix of the target's path (on a segment boundary).
+pub fn is_allowed(auth: &Auth, target: &str) -> bool {
+    if target.is_empty() || target.chars().any(|c| c.is_control() || c == '\\') {
+        return false;
+    }
+
+    if target.starts_with('/') {
+        // Resolve dot segments and reject `//host`, which is another site.
+        let base = Url::parse(RELATIVE_BASE).expect("valid base url");
+        let target = match base.join(target) {
+            Ok(url) if url.host_str() == base.host_str() => url,
+            _ => return false,
+        };
+
+        return auth
+            .return_to_allowlist
+            .iter()
+            .filter(|entry| entry.starts_with('/'))
+            .any(|entry| path_matches(entry, target.path()));
+    }
+
+    let target = match Url::parse(target) {
+        Ok(url) => url,
+        Err(_) => return false,
+    };
+    if !target.username().is_empty() || target.password().is_some() {
+        return false;
+    }
+
+    auth.return_to_allowlist
+        .iter()
+        .filter_map(|entry| Url::parse(entry).ok())
+        .any(|entry| {
+            entry.scheme() == target.scheme()
+                && entry.host_str().is_some()
+                && entry.host_str() == target.host_str()
+                && entry.port_or_known_default() == target.port_or_known_default()
+                && path_matches(entry.path(), target.path())
+        })
+}
+
+/// Returns the `return_to` target, if it is allowed.
+pub fn validated(auth: &Auth, target: Option<&String>) -> Option<String> {
+    match target {
+        Some(target) if is_allowed(auth, target) => Some(target.clone()),
+        Some(target) => {
+            tracing::warn!("Ignoring not allowed return_to target: {}", target);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Appends the `return_to` query parameter to a URL.
+pub fn append(url: &str, return_to: &str) -> String {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("return_to", return_to)
+        .finish();
+    let separator = if url.contains('?') { '&' } else { '?' };
+
+    format!("{}{}{}", url, separator, query)
+}
+
+fn path_matches(prefix: &str, path: &str) -> bool {
+    prefix == "/"
+        || path == prefix
+        || path.starts_with(prefix)
+            && (prefix.ends_with('/') || path[prefix.len()..].starts_with('/'))
+}
@@ -1,7 +1,7 @@
 use axum::{
     extract::Extension,
     middleware::{self},
-    routing::{get, get_service},
+    routing::{delete, get, get_service, post},
     Router,
 };
 use hyper::StatusCode;
@@ -10,8 +10,24 @@ use tower_http::services::ServeDir;
 use crate::{
     application::ApplicationState,
     driver::db,
-    handler::authentication::{claims, oidc_client_login, oidc_client_login_cb},
-    middleware::authentication::{jwt_auth_middleware, JwtClaims},
+    handler::{
+        api_key::{create_api_key, list_api_keys, revoke_api_key},
+        authentication::{
+            claims, list_identities, logout, oidc_client_link, oidc_client_login,
+            oidc_client_login_cb, refresh,
+        },
+        health::{healthz, readyz},
+        local_auth::{
+            local_change_password, local_login, local_register, local_request_password_reset,
+            local_reset_password,
+        },
+        metrics::metrics,
+        well_known::{jwks, openid_configuration},
+    },
+    middleware::{
+        authentication::{jwt_auth_middleware, JwtClaims},
+        metrics::track_metrics,
+    },
 };
 
 #[derive(Debug, Clone)]
@@ -35,6 +51,17 @@ impl ApplicationRouter {
             .route("/self", get(claims))
             .route("/oidc_login/:provider_name", get(oidc_client_login))
             .route("/oidc_login_cb/:provider_name", get(oidc_client_login_cb))
+            .route("/oidc_link/:provider_name", get(oidc_client_link))
+            .route("/identities", get(list_identities))
+            .route("/refresh", post(refresh))
+            .route("/logout", post(logout))
+            .route("/api_keys", get(list_api_keys).post(create_api_key))
+            .route("/api_keys/:id", delete(revoke_api_key))
+            .route("/local/register", post(local_register))
+            .route("/local/login", post(local_login))
+            .route("/local/password", post(local_change_password))
+            .route("/local/password_reset", post(local_request_password_reset))
+            .route("/local/password_reset/confirm", post(local_reset_password))
             .route_layer(middleware::from_fn(move |req, next| {
                 jwt_auth_middleware(req, next, cfg_clone.clone())
             }))
@@ -48,6 +75,48 @@ impl ApplicationRouter {
         self
     }
 
+    /// Takes existing ApplicationRouter and adds the JWK set of the JWT
+    /// signing keys and a minimal OpenID discovery document
+    pub(crate) fn with_well_known_routes(mut self) -> ApplicationRouter {
+        let wk: Router = Router::new()
+            .route("/.well-known/jwks.json", get(jwks))
+            .route(
+                "/.well-known/openid-configuration",
+                get(openid_configuration),
+            )
+            .layer(Extension(self.state.clone()));
+
+        self.router = self.router.merge(wk);
+
+        self
+    }
+
+    /// Takes existing ApplicationRouter and adds the liveness (`/healthz`)
+    /// and readiness (`/readyz`) probes
+    pub(crate) fn with_health_routes(mut self) -> ApplicationRouter {
+        let hr: Router = Router::new()
+            .route("/healthz", get(healthz))
+            .route("/readyz", get(readyz))
+            .layer(Extension(self.state.clone()));
+
+        self.router = self.router.merge(hr);
+
+        self
+    }
+
+    /// Takes existing ApplicationRouter and adds the Prometheus `/metrics`
+    /// route. Requests of all routes are tracked once the router is finalized.
+    pub(crate) fn with_metrics(mut self) -> ApplicationRouter {
+        self.state.metrics.enable();
+        let mr: Router = Router::new()
+            .route("/metrics", get(metrics))
+            .layer(Extension(self.state.clone()));
+
+        self.router = self.router.merge(mr);
+
+        self
+    }
+
     pub(crate) fn with_static_route(mut self, dir: (String, String)) -> ApplicationRouter {
         self.router = self.router.nest(
             dir.0.as_str(),
@@ -90,6 +159,16 @@ impl ApplicationRouter {
     }
 
     pub fn finalize(self, db: db::DB) -> Router {
-        self.router.layer(Extension(db))
+        let mut router = self.router;
+        if self.state.metrics.is_enabled() {
+            // Layers wrap every single route, so the matched route is known.
+            let metrics = self.state.metrics.clone();
+            router = router.layer(middleware::from_fn(move |req, next| {
+                track_metrics(req, next, metrics.clone())
+            }));
+        }
+
+        // Available on all routes, e.g. for the authentication extractors.
+        router.layer(Extension(self.state)).layer(Extension(db))
     }
 }
@@ -0,0 +1,264 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use axum::{
+    http::{header::HOST, HeaderMap, Uri},
+    response::Redirect,
+    Router,
+};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use futures::{future::BoxFuture, FutureExt};
+use tokio::sync::watch;
+
+use crate::configuration::Tls;
+
+/// A bound server, which runs until it is told to shut down and all its
+/// connections are closed.
+pub(crate) type ServerFuture = BoxFuture<'static, Result<()>>;
+
+/// Resolves once the shutdown of the application started.
+pub(crate) async fn shutdown_started(mut shutdown: watch::Receiver<bool>) {
+    while !*shutdown.borrow() {
+        if shutdown.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Binds the listener right away, so the address (e.g. a random port) is
+/// known before the application runs.
+pub(crate) fn bind_tcp(addr: SocketAddr) -> Result<TcpListener> {
+    let listener = TcpListener::bind(addr)
+        .map_err(|e| anyhow::anyhow!("Unable to bind \"{}\". Error was: {}", addr, e))?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+pub(crate) fn serve_http(
+    listener: TcpListener,
+    router: Router,
+    shutdown: watch::Receiver<bool>,
+) -> Result<ServerFuture> {
+    let server = axum::Server::from_tcp(listener)?
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(shutdown_started(shutdown));
+
+    Ok(async move { server.await.map_err(Into::into) }.boxed())
+}
+
+/// Loads certificate and key, which are reloaded on changes until the
+/// shutdown.
+pub(crate) async fn load_tls(tls: &Tls, shutdown: watch::Receiver<bool>) -> Result<RustlsConfig> {
+    let config = RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path)
+        .await
+        .map_err(|e| {
+            anyhow::anyhow!(
+                "Unable to load TLS certificate \"{}\" and key \"{}\". Error was: {}",
+                tls.cert_path,
+                tls.key_path,
+                e
+            )
+        })?;
+    spawn_reload(config.clone(), tls.clone(), shutdown);
+
+    Ok(config)
+}
+
+pub(crate) fn serve_https(
+    listener: TcpListener,
+    router: Router,
+    config: RustlsConfig,
+    shutdown: watch::Receiver<bool>,
+) -> Result<ServerFuture> {
+    let handle = Handle::new();
+    let graceful = handle.clone();
+    tokio::spawn(async move {
+        shutdown_started(shutdown).await;
+        graceful.graceful_shutdown(None);
+    });
+
+    let server = axum_server::from_tcp_rustls(listener, config)
+        .handle(handle)
+        .serve(router.into_make_service());
+
+    Ok(async move { server.await.map_err(Into::into) }.boxed())
+}
+
+/// Serves plain HTTP on a unix socket, e.g. behind a reverse proxy. A stale
+/// socket file is replaced and removed again after the shutdown.
+#[cfg(unix)]
+pub(crate) fn serve_unix(
+    path: &Path,
+    mode: Option<&str>,
+    router: Router,
+    shutdown: watch::Receiver<bool>,
+) -> Result<ServerFuture> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let mode = mode
+        .map(|mode| {
+            u32::from_str_radix(mode, 8)
+                .map_err(|e| anyhow::anyhow!("Invalid unix socket mode \"{}\": {}", mode, e))
+        })
+        .transpose()?;
+    let is_socket =
+        |path: &Path| std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket());
+    let bind = |path: &Path| {
+        if is_socket(path) {
+            std::fs::remove_file(path)?;
+        }
+        tokio::net::UnixListener::bind(path).map_err(|e| {
+            anyhow::anyhow!(
+                "Unable to bind \"unix:{}\". Error was: {}",
+                path.display(),
+                e
+            )
+        })
+    };
+
+    let listener = match mode {
+        // Bound under a temporary name, so the socket is never reachable
+        // with the permissions of the umask.
+        Some(mode) => {
+            if path.exists() && !is_socket(path) {
+                return Err(anyhow::anyhow!(
+                    "Unable to bind \"unix:{}\". Error was: File exists",
+                    path.display()
+                ));
+            }
+            let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+            tmp_name.push(".tmp");
+            let tmp_path = path.with_file_name(tmp_name);
+
+            let listener = bind(&tmp_path)?;
+            std::fs::set_permissions(&tmp_path, std::fs::Permissions::from_mode(mode))
+                .and_then(|_| std::fs::rename(&tmp_path, path))
+                .inspect_err(|_| {
+                    let _ = std::fs::remove_file(&tmp_path);
+                })?;
+            listener
+        }
+        None => bind(path)?,
+    };
+
+    // hyper ends the server on the first error of the stream, so errors are
+    // skipped, with a back-off unless only a single connection failed.
+    let incoming = hyper::server::accept::from_stream(futures::stream::unfold(
+        listener,
+        |listener| async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => return Some((Ok::<_, std::io::Error>(stream), listener)),
+                    Err(e) if is_connection_error(&e) => {
+                        tracing::debug!("Unix socket connection failed. Error was: {}", e);
+                    }
+                    Err(e) => {
+                        tracing::error!("Unable to accept on unix socket. Error was: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        },
+    ));
+    let server = hyper::Server::builder(incoming)
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(shutdown_started(shutdown));
+
+    let path = path.to_path_buf();
+    Ok(async move {
+        let result = server.await;
+        let _ = std::fs::remove_file(&path);
+        result.map_err(Into::into)
+    }
+    .boxed())
+}
+
+/// Errors of a single connection, which don't affect the listener.
+#[cfg(unix)]
+fn is_connection_error(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::ConnectionReset
+    )
+}
+
+#[cfg(not(unix))]
+pub(crate) fn serve_unix(
+    path: &Path,
+    _mode: Option<&str>,
+    _router: Router,
+    _shutdown: watch::Receiver<bool>,
+) -> Result<ServerFuture> {
+    Err(anyhow::anyhow!(
+        "Unix sockets are not supported on this platform: {}",
+        path.display()
+    ))
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    Path::new(path).metadata().and_then(|m| m.modified()).ok()
+}
+
+/// Reloads certificate and key, whenever one of the files changed, e.g.
+/// after a renewal.
+fn spawn_reload(config: RustlsConfig, tls: Tls, shutdown: watch::Receiver<bool>) {
+    tokio::spawn(async move {
+        let mut last_modified = (modified(&tls.cert_path), modified(&tls.key_path));
+        let mut interval = tokio::time::interval(Duration::from_secs(tls.reload_seconds));
+        let stopped = shutdown_started(shutdown);
+        tokio::pin!(stopped);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = &mut stopped => break,
+            }
+
+            let current = (modified(&tls.cert_path), modified(&tls.key_path));
+            if current == last_modified {
+                continue;
+            }
+
+            match config
+                .reload_from_pem_file(&tls.cert_path, &tls.key_path)
+                .await
+            {
+                Ok(()) => {
+                    tracing::info!("Reloaded TLS certificate \"{}\"", tls.cert_path);
+                    last_modified = current;
+                }
+                // Possibly only one of the files is written yet, retried on the next tick.
+                Err(e) => tracing::warn!("Unable to reload TLS certificate. Error was: {}", e),
+            }
+        }
+    });
+}
+
+/// Plain HTTP service, which permanently redirects all requests to the
+/// HTTPS listener on `https_port`.
+pub(crate) fn https_redirect(https_port: u16) -> Router {
+    Router::new().fallback(axum::routing::any(
+        move |headers: HeaderMap, uri: Uri| async move {
+            let host = headers
+                .get(HOST)
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.parse::<axum::http::uri::Authority>().ok())
+                .map(|a| a.host().to_string())
+                .unwrap_or_else(|| "localhost".into());
+            let port = if https_port == 443 {
+                String::new()
+            } else {
+                format!(":{}", https_port)
+            };
+            let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+
+            Redirect::permanent(&format!("https://{}{}{}", host, port, path))
+        },
+    ))
+}
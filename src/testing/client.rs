@@ -1,6 +1,12 @@
 use async_trait::async_trait;
+use openidconnect::{CsrfToken, Nonce};
 
-use crate::{configuration::Configuration, middleware::authentication::create_jwt};
+use crate::{
+    configuration::Configuration,
+    handler::authentication::OidcLoginState,
+    keys::KeySet,
+    middleware::authentication::{create_jwt, create_jwt_from_claims, JwtClaims},
+};
 
 pub struct AureliaTestClient {
     pub port: u16,
@@ -44,6 +50,31 @@ impl TestClient for AureliaTestClient {
 }
 
 impl AureliaTestClient {
+    /// Claims of a test user with the subject `sub`, e.g. for
+    /// `authenticated_with_claims`.
+    pub fn claims(&self, sub: &str) -> JwtClaims {
+        JwtClaims::new(
+            sub.into(),
+            "aurelia".into(),
+            "user@example.com".into(),
+            "Toni".into(),
+            "Tester".into(),
+            None,
+            self.configuration
+                .application
+                .auth
+                .jwt_expiration_offset_seconds,
+        )
+    }
+
+    /// Authenticates with a JWT for the given claims, e.g. with roles.
+    pub fn authenticated_with_claims(&mut self, claims: JwtClaims) {
+        let keys = KeySet::new(&self.configuration.application.auth).unwrap();
+        self.set_jwt(
+            create_jwt_from_claims(&self.configuration, &keys, claims, Some(true)).unwrap(),
+        )
+    }
+
     pub fn uri(&self, uri_part: &str) -> String {
         format!("http://localhost:{}{}", self.port, uri_part)
     }
@@ -56,12 +87,76 @@ impl AureliaTestClient {
         Ok(req.send().await?)
     }
 
+    pub async fn request_post(&self, uri: &str) -> anyhow::Result<reqwest::Response> {
+        let mut req = self.http_client.post(uri);
+        if let Some(jwt) = &self.client_jwt {
+            req = req.header("Authorization", &format!("Bearer {}", jwt));
+        }
+        Ok(req.send().await?)
+    }
+
+    pub async fn request_post_json<T: serde::Serialize + ?Sized>(
+        &self,
+        uri: &str,
+        json: &T,
+    ) -> anyhow::Result<reqwest::Response> {
+        let mut req = self.http_client.post(uri).json(json);
+        if let Some(jwt) = &self.client_jwt {
+            req = req.header("Authorization", &format!("Bearer {}", jwt));
+        }
+        Ok(req.send().await?)
+    }
+
+    pub async fn request_delete(&self, uri: &str) -> anyhow::Result<reqwest::Response> {
+        let mut req = self.http_client.delete(uri);
+        if let Some(jwt) = &self.client_jwt {
+            req = req.header("Authorization", &format!("Bearer {}", jwt));
+        }
+        Ok(req.send().await?)
+    }
+
     pub async fn get_oidc_login(&self, provider_name: &str) -> anyhow::Result<reqwest::Response> {
         Ok(self
             .request_get(&self.uri(&format!("/auth/oidc_login/{}", provider_name)))
             .await?)
     }
 
+    /// Signed cookie of a running login with `provider_name`, whose callback
+    /// has to carry `csrf_token` as `state`.
+    pub fn oidc_login_state_cookie(&self, provider_name: &str, csrf_token: &str) -> String {
+        let auth = &self.configuration.application.auth;
+        let login_state = OidcLoginState::new(
+            provider_name,
+            &CsrfToken::new(csrf_token.into()),
+            &Nonce::new_random(),
+            None,
+            None,
+            None,
+            auth.oidc_state_expiration_seconds,
+        );
+        format!(
+            "{}={}",
+            auth.oidc_state_cookie_name,
+            login_state.encode(auth).unwrap()
+        )
+    }
+
+    /// `return_to` target of the login state cookie, which `response` sets.
+    pub fn oidc_login_return_to(&self, response: &reqwest::Response) -> Option<String> {
+        let auth = &self.configuration.application.auth;
+        let prefix = format!("{}=", auth.oidc_state_cookie_name);
+        let cookie = response
+            .headers()
+            .get_all("Set-Cookie")
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .find_map(|h| h.strip_prefix(&prefix))
+            .expect("No oidc login state cookie set");
+        let value = cookie.split(';').next().unwrap_or_default();
+
+        OidcLoginState::decode(value, auth).unwrap().return_to
+    }
+
     pub async fn _get_full_uri(&self, uri: String) -> anyhow::Result<reqwest::Response> {
         Ok(self.request_get(&uri).await?)
     }
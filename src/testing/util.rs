@@ -1,9 +1,11 @@
 use crate::{
-    application::{Application, AureliaAppHandler},
+    application::{AppHandler, Application, AureliaAppHandler},
+    cli::migrate_aurelia_database,
     configuration,
     telemetry::init_subscriber,
 };
 use once_cell::sync::Lazy;
+use tokio::sync::OnceCell;
 
 use crate::testing::client::TestClient;
 
@@ -12,18 +14,52 @@ static TRACING: Lazy<()> = Lazy::new(|| {
     init_subscriber();
 });
 
+// Aurelia's own tables are migrated once per test run
+static MIGRATIONS: OnceCell<()> = OnceCell::const_new();
+
 pub async fn spawn_test_application<F, T>(cfg_fn: F) -> anyhow::Result<T>
 where
     F: FnOnce(Application) -> Application,
     T: TestClient,
+{
+    spawn_test_application_with(cfg_fn, |_| {}).await
+}
+
+/// Like `spawn_test_application`, but allows to adjust the configuration
+/// before the application starts.
+pub async fn spawn_test_application_with<F, C, T>(cfg_fn: F, cfg_mod: C) -> anyhow::Result<T>
+where
+    F: FnOnce(Application) -> Application,
+    C: FnOnce(&mut configuration::Configuration),
+    T: TestClient,
+{
+    spawn_test_application_with_handlers(cfg_fn, cfg_mod, Box::new(AureliaAppHandler {})).await
+}
+
+/// Like `spawn_test_application_with`, but with custom `AppHandler`s.
+pub async fn spawn_test_application_with_handlers<F, C, T>(
+    cfg_fn: F,
+    cfg_mod: C,
+    handlers: Box<dyn AppHandler>,
+) -> anyhow::Result<T>
+where
+    F: FnOnce(Application) -> Application,
+    C: FnOnce(&mut configuration::Configuration),
+    T: TestClient,
 {
     Lazy::force(&TRACING);
     let mut test_cfg = configuration::Configuration::new(None)?;
-    test_cfg.http.port = 0;
+    // Bound up front, so the configuration (e.g. the default `jwt_issuer`)
+    // knows the random port the application listens on.
+    let listener = std::net::TcpListener::bind((test_cfg.http.address, 0))?;
+    test_cfg.http.port = listener.local_addr()?.port();
+    cfg_mod(&mut test_cfg);
+    MIGRATIONS
+        .get_or_try_init(|| migrate_aurelia_database(&test_cfg))
+        .await?;
 
-    let app =
-        Application::init(Some(test_cfg.clone()), Some(Box::new(AureliaAppHandler {}))).await?;
-    let prepared_app = cfg_fn(app).prepare().await.unwrap();
+    let app = Application::init(Some(test_cfg.clone()), Some(handlers)).await?;
+    let prepared_app = cfg_fn(app).with_listener(listener).prepare().await.unwrap();
 
     let client = T::new(prepared_app.get_port(), test_cfg);
 
@@ -45,3 +81,29 @@ where
         .await
         .expect("Unable to create test application")
 }
+
+pub async fn get_tc_with<F, C, T>(cfg_fn: F, cfg_mod: C) -> T
+where
+    F: FnOnce(Application) -> Application,
+    C: FnOnce(&mut configuration::Configuration),
+    T: TestClient,
+{
+    spawn_test_application_with(cfg_fn, cfg_mod)
+        .await
+        .expect("Unable to create test application")
+}
+
+pub async fn get_tc_with_handlers<F, C, T>(
+    cfg_fn: F,
+    cfg_mod: C,
+    handlers: Box<dyn AppHandler>,
+) -> T
+where
+    F: FnOnce(Application) -> Application,
+    C: FnOnce(&mut configuration::Configuration),
+    T: TestClient,
+{
+    spawn_test_application_with_handlers(cfg_fn, cfg_mod, handlers)
+        .await
+        .expect("Unable to create test application")
+}
@@ -0,0 +1,274 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use aurelia::{
+    anyhow,
+    application::{AppHandler, Application},
+    async_trait,
+    configuration::Configuration,
+    crypto,
+    database::{entity::oidc_session::OidcSession, init_connection},
+    driver::db,
+    middleware::authentication::JwtClaims,
+    serde_json::{json, Value},
+    testing::{
+        client::{AureliaTestClient, TestClient},
+        util::get_tc_with_handlers,
+    },
+    uuid::Uuid,
+};
+use chrono::Utc;
+
+use crate::{cfg_fn, oidc_provider};
+
+/// Purpose of the refresh token encryption in the oidc session.
+const REFRESH_TOKEN_PURPOSE: &str = "oidc_refresh_token";
+
+/// Records the called hooks, rejects the subject `blocked` and refreshes of
+/// the subject `frozen`.
+#[derive(Debug, Clone, Default)]
+struct RecordingHandler {
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+impl RecordingHandler {
+    fn record(&self, event: String) {
+        self.events.lock().unwrap().push(event);
+    }
+
+    fn recorded(&self, event: &str) -> bool {
+        self.events.lock().unwrap().iter().any(|e| e == event)
+    }
+}
+
+#[async_trait]
+impl AppHandler for RecordingHandler {
+    async fn on_login_callback(
+        &self,
+        _claims: &mut JwtClaims,
+        _config: &Configuration,
+        _db: db::DB,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn on_login_failure(
+        &self,
+        provider_name: &str,
+        error: &str,
+        _config: &Configuration,
+        _db: db::DB,
+    ) -> anyhow::Result<()> {
+        self.record(format!("login_failure:{}:{}", provider_name, error));
+        Ok(())
+    }
+
+    async fn on_logout(
+        &self,
+        claims: &JwtClaims,
+        _config: &Configuration,
+        _db: db::DB,
+    ) -> anyhow::Result<()> {
+        self.record(format!("logout:{}", claims.sub));
+        Ok(())
+    }
+
+    async fn on_request_authenticated(
+        &self,
+        claims: &JwtClaims,
+        _config: &Configuration,
+        _db: db::DB,
+    ) -> anyhow::Result<()> {
+        if claims.sub == "blocked" {
+            return Err(anyhow::anyhow!("User is blocked"));
+        }
+        self.record(format!("authenticated:{}", claims.sub));
+        Ok(())
+    }
+
+    async fn on_token_refresh(
+        &self,
+        claims: &mut JwtClaims,
+        _config: &Configuration,
+        _db: db::DB,
+    ) -> anyhow::Result<()> {
+        if claims.sub == "frozen" {
+            return Err(anyhow::anyhow!("User is frozen"));
+        }
+        claims.roles.push("refreshed".into());
+        Ok(())
+    }
+
+    async fn on_startup(&self, _config: &Configuration, _db: db::DB) -> anyhow::Result<()> {
+        self.record("startup".into());
+        Ok(())
+    }
+
+    async fn on_shutdown(&self, _config: &Configuration) -> anyhow::Result<()> {
+        self.record("shutdown".into());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_lifecycle_hooks() {
+    let handler = RecordingHandler::default();
+    let mut c =
+        get_tc_with_handlers::<_, _, AureliaTestClient>(cfg_fn, |_| {}, Box::new(handler.clone()))
+            .await;
+    assert!(handler.recorded("startup"));
+
+    let sub = Uuid::new_v4().to_string();
+    c.authenticated_with_claims(c.claims(&sub));
+    let r = c.request_get(&c.uri("/auth/self")).await.unwrap();
+    assert_eq!(r.status(), 200);
+    assert!(handler.recorded(&format!("authenticated:{}", sub)));
+
+    let r = c.request_post(&c.uri("/auth/logout")).await.unwrap();
+    assert_eq!(r.status(), 303);
+    assert!(handler.recorded(&format!("logout:{}", sub)));
+}
+
+#[tokio::test]
+async fn test_request_authenticated_rejects() {
+    let mut c = get_tc_with_handlers::<_, _, AureliaTestClient>(
+        cfg_fn,
+        |_| {},
+        Box::new(RecordingHandler::default()),
+    )
+    .await;
+
+    c.authenticated_with_claims(c.claims("blocked"));
+    let r = c.request_get(&c.uri("/auth/self")).await.unwrap();
+    assert_eq!(r.text().await.unwrap(), "null");
+}
+
+#[tokio::test]
+async fn test_login_failure() {
+    let handler = RecordingHandler::default();
+    let c = get_tc_with_handlers::<_, _, AureliaTestClient>(
+        cfg_fn,
+        |cfg| cfg.application.auth.local.enabled = true,
+        Box::new(handler.clone()),
+    )
+    .await;
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/login"),
+            &json!({"email": "nobody@example.com", "password": "secret123"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 401);
+    assert!(handler.recorded("login_failure:local:invalid_credentials"));
+}
+
+/// Authenticates `sub` with an oidc session of the mocked provider, which
+/// allows to refresh the JWT.
+async fn authenticated_with_session(c: &mut AureliaTestClient, sub: &str) -> Uuid {
+    let db = init_connection(&c.configuration).await;
+    let session = OidcSession {
+        id: Uuid::new_v4(),
+        provider_name: oidc_provider::PROVIDER.into(),
+        subject: sub.into(),
+        refresh_token: Some(
+            crypto::encrypt(
+                &c.configuration.application.auth.jwt_secret,
+                REFRESH_TOKEN_PURPOSE,
+                b"refresh-token",
+            )
+            .unwrap(),
+        ),
+        id_token: None,
+        created_at: Utc::now(),
+        expires_at: Utc::now() + chrono::Duration::hours(1),
+    };
+    session.insert(&db).await.unwrap();
+
+    let mut claims = c.claims(sub);
+    claims.sid = Some(session.id.to_string());
+    c.authenticated_with_claims(claims);
+    session.id
+}
+
+async fn refresh_tc(handler: &RecordingHandler) -> AureliaTestClient {
+    let mock = oidc_provider::spawn();
+    get_tc_with_handlers::<_, _, AureliaTestClient>(
+        cfg_fn,
+        |cfg| oidc_provider::configure(cfg, &mock.issuer_url),
+        Box::new(handler.clone()),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn test_token_refresh_adjusts_claims() {
+    let handler = RecordingHandler::default();
+    let mut c = refresh_tc(&handler).await;
+    let sid = authenticated_with_session(&mut c, &Uuid::new_v4().to_string()).await;
+
+    let r = c.request_post(&c.uri("/auth/refresh")).await.unwrap();
+    assert_eq!(r.status(), 200);
+    let token: Value = r.json().await.unwrap();
+    c.set_jwt(token["token"].as_str().unwrap().into());
+
+    let claims: Value = c
+        .request_get(&c.uri("/auth/self"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(claims["roles"], json!(["refreshed"]));
+    assert_eq!(claims["sid"], sid.to_string());
+
+    // The provider rotated the refresh token.
+    let db = init_connection(&c.configuration).await;
+    let session = OidcSession::get(sid, &db).await.unwrap().unwrap();
+    let refresh_token = crypto::decrypt(
+        &c.configuration.application.auth.jwt_secret,
+        REFRESH_TOKEN_PURPOSE,
+        &session.refresh_token.unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        refresh_token,
+        oidc_provider::ROTATED_REFRESH_TOKEN.as_bytes()
+    );
+}
+
+#[tokio::test]
+async fn test_token_refresh_rejects() {
+    let handler = RecordingHandler::default();
+    let mut c = refresh_tc(&handler).await;
+    authenticated_with_session(&mut c, "frozen").await;
+
+    let r = c.request_post(&c.uri("/auth/refresh")).await.unwrap();
+    assert_eq!(r.status(), 401);
+}
+
+#[tokio::test]
+async fn test_shutdown_hook() {
+    let handler = RecordingHandler::default();
+    let mut cfg = Configuration::new(None).unwrap();
+    cfg.http.port = 0;
+
+    let app = Application::init(Some(cfg), Some(Box::new(handler.clone())))
+        .await
+        .unwrap();
+    let prepared_app = cfg_fn(app).prepare().await.unwrap();
+    let shutdown = prepared_app.shutdown_handle();
+    let server = tokio::spawn(prepared_app.run());
+    assert!(!handler.recorded("shutdown"));
+
+    shutdown.shutdown();
+    tokio::time::timeout(Duration::from_secs(10), server)
+        .await
+        .expect("Server didn't shut down")
+        .unwrap()
+        .unwrap();
+    assert!(handler.recorded("shutdown"));
+}
@@ -0,0 +1,100 @@
+use aurelia::{
+    serde_json::{json, Value},
+    testing::{
+        client::{AureliaTestClient, TestClient},
+        util::get_tc,
+    },
+};
+
+use crate::cfg_fn;
+
+#[tokio::test]
+async fn test_api_keys_require_authentication() {
+    let c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = c.request_get(&c.uri("/auth/api_keys")).await.unwrap();
+    assert_eq!(r.status(), 401);
+}
+
+#[tokio::test]
+async fn test_api_key_lifecycle() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+    c.authenticated().await;
+
+    let r = c
+        .request_post_json(&c.uri("/auth/api_keys"), &json!({"name": "ci"}))
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 201);
+    let created: Value = r.json().await.unwrap();
+    let key = created["key"].as_str().unwrap().to_string();
+    let id = created["id"].as_str().unwrap().to_string();
+    assert!(key.starts_with(&format!(
+        "{}_",
+        c.configuration.application.auth.api_key_prefix
+    )));
+
+    let r = c.request_get(&c.uri("/auth/api_keys")).await.unwrap();
+    let keys: Value = r.json().await.unwrap();
+    assert!(keys
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|k| k["id"] == id.as_str() && k.get("key").is_none()));
+
+    // The key authenticates as prefixed Bearer token ...
+    let mut kc = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+    kc.set_jwt(key.clone());
+    let r = kc.request_get(&kc.uri("/auth/self")).await.unwrap();
+    let claims: Value = r.json().await.unwrap();
+    assert_eq!(claims["api_key"], id.as_str());
+
+    // ... and in the API key header
+    let r = aurelia::reqwest::Client::new()
+        .get(kc.uri("/auth/self"))
+        .header(
+            c.configuration
+                .application
+                .auth
+                .api_key_header_name
+                .as_str(),
+            key.as_str(),
+        )
+        .send()
+        .await
+        .unwrap();
+    let claims: Value = r.json().await.unwrap();
+    assert_eq!(claims["api_key"], id.as_str());
+
+    // API keys can't create further keys
+    let r = kc
+        .request_post_json(&kc.uri("/auth/api_keys"), &json!({"name": "nested"}))
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 403);
+
+    let r = c
+        .request_delete(&c.uri(&format!("/auth/api_keys/{}", id)))
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 204);
+
+    let r = kc.request_get(&kc.uri("/auth/self")).await.unwrap();
+    let claims: Value = r.json().await.unwrap();
+    assert!(claims.is_null());
+}
+
+#[tokio::test]
+async fn test_api_key_scopes_require_permission() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+    c.authenticated().await;
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/api_keys"),
+            &json!({"name": "ci", "scopes": ["recipe:write"]}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 403);
+}
@@ -1,6 +1,19 @@
-use aurelia::testing::{client::AureliaTestClient, util::get_tc};
+use std::collections::HashMap;
 
-use crate::cfg_fn;
+use aurelia::{
+    configuration::Oidc,
+    serde_json::{json, Value},
+    testing::{
+        client::{AureliaTestClient, TestClient},
+        util::{get_tc, get_tc_with},
+    },
+};
+use openssl::sha::sha256;
+
+use crate::{
+    cfg_fn,
+    oidc_provider::{self, MockProvider},
+};
 
 #[tokio::test]
 async fn test_oidc_login() {
@@ -46,3 +59,494 @@ async fn test_oidc_login_cb() {
     // TODO: read jwt
     assert!(provider_response.status() == 200);
 }
+
+#[tokio::test]
+async fn test_oidc_login_sets_state_cookie() {
+    let c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+    let oidc_provider = c.configuration.get_oidc_provider("auth0").unwrap();
+
+    let r = c
+        .get_oidc_login(&oidc_provider.provider_name)
+        .await
+        .unwrap();
+
+    assert!(match r.headers().get("Set-Cookie") {
+        Some(s) => s.to_str().unwrap().starts_with(&format!(
+            "{}=",
+            c.configuration.application.auth.oidc_state_cookie_name
+        )),
+        None => false,
+    });
+}
+
+#[tokio::test]
+async fn test_oidc_login_with_return_to() {
+    let mock = oidc_provider::spawn();
+    let c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |cfg| {
+        oidc_provider::configure(cfg, &mock.issuer_url)
+    })
+    .await;
+
+    let r = c
+        .request_get(&c.uri(&format!(
+            "/auth/oidc_login/{}?return_to=%2Fdeep%2Flink",
+            oidc_provider::PROVIDER
+        )))
+        .await
+        .unwrap();
+
+    assert_eq!(r.status(), 303);
+    assert_eq!(c.oidc_login_return_to(&r).as_deref(), Some("/deep/link"));
+}
+
+#[tokio::test]
+async fn test_oidc_login_drops_offsite_return_to() {
+    let mock = oidc_provider::spawn();
+    let c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |cfg| {
+        oidc_provider::configure(cfg, &mock.issuer_url)
+    })
+    .await;
+
+    let r = c
+        .request_get(&c.uri(&format!(
+            "/auth/oidc_login/{}?return_to=https%3A%2F%2Fevil.example%2Fphish",
+            oidc_provider::PROVIDER
+        )))
+        .await
+        .unwrap();
+
+    assert_eq!(r.status(), 303);
+    assert_eq!(c.oidc_login_return_to(&r), None);
+}
+
+#[tokio::test]
+async fn test_oidc_login_cb_without_state() {
+    let c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = c
+        .request_get(&c.uri("/auth/oidc_login_cb/auth0?code=abc&state=def"))
+        .await
+        .unwrap();
+
+    assert_eq!(r.status(), 302);
+    assert_eq!(
+        r.headers().get("Location").unwrap(),
+        format!(
+            "{}?error=invalid_state",
+            c.configuration.application.auth.redirect_on_login_error
+        )
+        .as_str()
+    );
+}
+
+async fn get_with_cookie(c: &AureliaTestClient, uri: &str, cookie: &str) -> reqwest::Response {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(c.uri(uri))
+        .header("Cookie", cookie)
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Client of an application, which logs in at the mock provider.
+async fn mock_tc<F: FnOnce(&mut Oidc)>(mock: &MockProvider, adjust: F) -> AureliaTestClient {
+    get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |cfg| {
+        oidc_provider::configure(cfg, &mock.issuer_url);
+        adjust(
+            cfg.application
+                .auth
+                .oidc
+                .get_mut(oidc_provider::PROVIDER)
+                .unwrap(),
+        );
+    })
+    .await
+}
+
+fn query_param(url: &reqwest::Url, name: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.into_owned())
+}
+
+/// Starts a login at the mock provider and returns the authorization URL and
+/// the login state cookie.
+async fn start_mock_login(c: &AureliaTestClient) -> (reqwest::Url, String) {
+    let r = c.get_oidc_login(oidc_provider::PROVIDER).await.unwrap();
+    assert_eq!(r.status(), 303);
+
+    let location = r.headers().get("Location").unwrap().to_str().unwrap();
+    let cookie = r.headers().get("Set-Cookie").unwrap().to_str().unwrap();
+    (
+        reqwest::Url::parse(location).unwrap(),
+        cookie.split(';').next().unwrap().to_string(),
+    )
+}
+
+/// Returns from the mock provider with an authorization code.
+async fn finish_mock_login(
+    c: &AureliaTestClient,
+    authorization_url: &reqwest::Url,
+    cookie: &str,
+) -> reqwest::Response {
+    let state = query_param(authorization_url, "state").unwrap();
+    get_with_cookie(
+        c,
+        &format!(
+            "/auth/oidc_login_cb/{}?code=mock-code&state={}",
+            oidc_provider::PROVIDER,
+            state
+        ),
+        cookie,
+    )
+    .await
+}
+
+/// Logs in at the mock provider and returns the JWT of the session cookie.
+async fn mock_login(c: &AureliaTestClient, mock: &MockProvider) -> String {
+    let auth = &c.configuration.application.auth;
+
+    let (authorization_url, cookie) = start_mock_login(c).await;
+    mock.set_nonce(query_param(&authorization_url, "nonce"));
+    let r = finish_mock_login(c, &authorization_url, &cookie).await;
+
+    assert_eq!(r.status(), 302);
+    assert_eq!(
+        r.headers().get("Location").unwrap(),
+        auth.redirect_on_login_success.as_str()
+    );
+    let prefix = format!("{}=", auth.jwt_cookie_name);
+    r.headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .filter_map(|h| h.to_str().ok()?.strip_prefix(&prefix))
+        .map(|h| h.split(';').next().unwrap().to_string())
+        .find(|jwt| !jwt.is_empty())
+        .expect("No JWT cookie set")
+}
+
+async fn self_claims(c: &AureliaTestClient) -> Value {
+    let r = c.request_get(&c.uri("/auth/self")).await.unwrap();
+    r.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_oidc_login_cb_with_id_token() {
+    let mock = oidc_provider::spawn();
+    let mut c = mock_tc(&mock, |_| {}).await;
+
+    let jwt = mock_login(&c, &mock).await;
+    c.set_jwt(jwt);
+    assert_eq!(self_claims(&c).await["email"], oidc_provider::EMAIL);
+}
+
+#[tokio::test]
+async fn test_oidc_login_cb_nonce_mismatch() {
+    let mock = oidc_provider::spawn();
+    let c = mock_tc(&mock, |_| {}).await;
+
+    // E.g. an ID token of another login is replayed.
+    let (authorization_url, cookie) = start_mock_login(&c).await;
+    mock.set_nonce(Some("replayed-nonce".into()));
+    let r = finish_mock_login(&c, &authorization_url, &cookie).await;
+
+    assert_eq!(r.status(), 302);
+    assert_eq!(
+        r.headers().get("Location").unwrap(),
+        format!(
+            "{}?error=invalid_id_token",
+            c.configuration.application.auth.redirect_on_login_error
+        )
+        .as_str()
+    );
+    let jwt_cookie = format!("{}=", c.configuration.application.auth.jwt_cookie_name);
+    assert!(!r
+        .headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .any(|h| h.to_str().unwrap().starts_with(&jwt_cookie)));
+}
+
+fn scopes(authorization_url: &reqwest::Url) -> Vec<String> {
+    let mut scopes: Vec<String> = query_param(authorization_url, "scope")
+        .unwrap()
+        .split(' ')
+        .map(String::from)
+        .collect();
+    scopes.sort();
+    scopes
+}
+
+#[tokio::test]
+async fn test_oidc_login_scopes_and_params() {
+    let mock = oidc_provider::spawn();
+    let c = mock_tc(&mock, |provider| {
+        provider.client_scopes = vec!["openid".into(), "email".into(), "offline_access".into()];
+        provider.authorization_params = HashMap::from([
+            ("prompt".into(), "login".into()),
+            ("audience".into(), "api".into()),
+        ]);
+    })
+    .await;
+
+    let (authorization_url, _) = start_mock_login(&c).await;
+    assert_eq!(
+        scopes(&authorization_url),
+        vec!["email", "offline_access", "openid"]
+    );
+    assert_eq!(
+        query_param(&authorization_url, "prompt").as_deref(),
+        Some("login")
+    );
+    assert_eq!(
+        query_param(&authorization_url, "audience").as_deref(),
+        Some("api")
+    );
+}
+
+#[tokio::test]
+async fn test_oidc_login_legacy_scope_string() {
+    let mock = oidc_provider::spawn();
+    // The single string form of earlier configurations
+    let c = mock_tc(&mock, |provider| {
+        provider.client_scopes = vec!["openid email profile".into()];
+    })
+    .await;
+
+    let (authorization_url, _) = start_mock_login(&c).await;
+    assert_eq!(
+        scopes(&authorization_url),
+        vec!["email", "openid", "profile"]
+    );
+}
+
+#[tokio::test]
+async fn test_oidc_login_client_role() {
+    let mock = oidc_provider::spawn();
+    let mut c = mock_tc(&mock, |provider| {
+        provider.client_role = "mock-client".into()
+    })
+    .await;
+
+    let jwt = mock_login(&c, &mock).await;
+    c.set_jwt(jwt);
+    assert_eq!(self_claims(&c).await["client_role"], "mock-client");
+}
+
+#[tokio::test]
+async fn test_oidc_login_pkce() {
+    let mock = oidc_provider::spawn();
+    let c = mock_tc(&mock, |provider| provider.pkce = true).await;
+
+    let (authorization_url, cookie) = start_mock_login(&c).await;
+    let challenge = query_param(&authorization_url, "code_challenge").unwrap();
+    assert_eq!(
+        query_param(&authorization_url, "code_challenge_method").as_deref(),
+        Some("S256")
+    );
+
+    finish_mock_login(&c, &authorization_url, &cookie).await;
+    let token_request = mock.token_requests().pop().unwrap();
+    assert_eq!(token_request["code"], "mock-code");
+    let verifier = &token_request["code_verifier"];
+    assert_eq!(
+        base64::encode_config(sha256(verifier.as_bytes()), base64::URL_SAFE_NO_PAD),
+        challenge
+    );
+}
+
+#[tokio::test]
+async fn test_oidc_login_without_pkce() {
+    let mock = oidc_provider::spawn();
+    let c = mock_tc(&mock, |provider| provider.pkce = false).await;
+
+    let (authorization_url, cookie) = start_mock_login(&c).await;
+    assert_eq!(query_param(&authorization_url, "code_challenge"), None);
+    assert_eq!(
+        query_param(&authorization_url, "code_challenge_method"),
+        None
+    );
+
+    finish_mock_login(&c, &authorization_url, &cookie).await;
+    let token_request = mock.token_requests().pop().unwrap();
+    assert_eq!(token_request["code"], "mock-code");
+    assert!(!token_request.contains_key("code_verifier"));
+}
+
+#[tokio::test]
+async fn test_oidc_login_cb_rejects_state_signed_with_jwt_secret() {
+    let c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+    let auth = &c.configuration.application.auth;
+
+    // E.g. a HS256 JWT, which Aurelia issued itself
+    let forged = jsonwebtoken::encode(
+        &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS256),
+        &json!({
+            "provider": "auth0",
+            "csrf_token": "def",
+            "nonce": "nonce",
+            "pkce_verifier": null,
+            "exp": chrono::Utc::now().timestamp() + 300,
+        }),
+        &jsonwebtoken::EncodingKey::from_secret(auth.jwt_secret.as_ref()),
+    )
+    .unwrap();
+
+    let r = get_with_cookie(
+        &c,
+        "/auth/oidc_login_cb/auth0?error=access_denied&state=def",
+        &format!("{}={}", auth.oidc_state_cookie_name, forged),
+    )
+    .await;
+    assert_eq!(r.status(), 302);
+    assert_eq!(
+        r.headers().get("Location").unwrap(),
+        format!("{}?error=invalid_state", auth.redirect_on_login_error).as_str()
+    );
+}
+
+#[tokio::test]
+async fn test_oidc_login_cb_provider_error() {
+    let c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = get_with_cookie(
+        &c,
+        "/auth/oidc_login_cb/auth0?error=access_denied&error_description=denied&state=def",
+        &c.oidc_login_state_cookie("auth0", "def"),
+    )
+    .await;
+
+    assert_eq!(r.status(), 302);
+    assert_eq!(
+        r.headers().get("Location").unwrap(),
+        format!(
+            "{}?error=provider_error&provider_error=access_denied",
+            c.configuration.application.auth.redirect_on_login_error
+        )
+        .as_str()
+    );
+}
+
+#[tokio::test]
+async fn test_oidc_login_cb_provider_error_requires_state() {
+    let c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+    let invalid_state = format!(
+        "{}?error=invalid_state",
+        c.configuration.application.auth.redirect_on_login_error
+    );
+
+    // No login was started.
+    let r = c
+        .request_get(&c.uri(
+            "/auth/oidc_login_cb/auth0?error=access_denied&error_description=denied&state=def",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 302);
+    assert_eq!(r.headers().get("Location").unwrap(), invalid_state.as_str());
+
+    // The state does not match the running login.
+    let r = get_with_cookie(
+        &c,
+        "/auth/oidc_login_cb/auth0?error=access_denied&state=forged",
+        &c.oidc_login_state_cookie("auth0", "def"),
+    )
+    .await;
+    assert_eq!(r.status(), 302);
+    assert_eq!(r.headers().get("Location").unwrap(), invalid_state.as_str());
+}
+
+#[tokio::test]
+async fn test_refresh_without_sid() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = c.request_post(&c.uri("/auth/refresh")).await.unwrap();
+    assert_eq!(r.status(), 401);
+
+    // JWTs created outside of an OIDC login don't carry a refresh token
+    c.authenticated().await;
+    let r = c.request_post(&c.uri("/auth/refresh")).await.unwrap();
+    assert_eq!(r.status(), 401);
+}
+
+#[tokio::test]
+async fn test_logout() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+    c.authenticated().await;
+
+    let r = c.request_post(&c.uri("/auth/logout")).await.unwrap();
+
+    assert_eq!(r.status(), 303);
+    assert_eq!(
+        r.headers().get("Location").unwrap(),
+        c.configuration.application.auth.redirect_on_logout.as_str()
+    );
+    assert!(r
+        .headers()
+        .get("Set-Cookie")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .contains("Max-Age=0"));
+
+    // The JWT is revoked, even though it is not expired yet
+    let r = c.request_get(&c.uri("/auth/self")).await.unwrap();
+    assert_eq!(r.text().await.unwrap(), "null");
+}
+
+#[tokio::test]
+async fn test_logout_requires_post() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+    c.authenticated().await;
+
+    let r = c.request_get(&c.uri("/auth/logout")).await.unwrap();
+    assert_eq!(r.status(), 405);
+
+    // The JWT is still valid
+    let r = c.request_get(&c.uri("/auth/self")).await.unwrap();
+    assert_ne!(r.text().await.unwrap(), "null");
+}
+
+#[tokio::test]
+async fn test_oidc_link_requires_login() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = c
+        .request_get(&c.uri("/auth/oidc_link/auth0"))
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 401);
+
+    c.authenticated().await;
+    let r = c
+        .request_get(&c.uri("/auth/oidc_link/auth0"))
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 303);
+    assert!(r
+        .headers()
+        .get("Set-Cookie")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with(&format!(
+            "{}=",
+            c.configuration.application.auth.oidc_state_cookie_name
+        )));
+}
+
+#[tokio::test]
+async fn test_list_identities() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = c.request_get(&c.uri("/auth/identities")).await.unwrap();
+    assert_eq!(r.status(), 401);
+
+    c.authenticated().await;
+    let r = c.request_get(&c.uri("/auth/identities")).await.unwrap();
+    assert_eq!(r.status(), 200);
+    assert_eq!(r.text().await.unwrap(), "[]");
+}
@@ -0,0 +1,115 @@
+use aurelia::{
+    anyhow,
+    application::{AppHandler, Application},
+    async_trait,
+    configuration::Configuration,
+    driver::db,
+    health::CheckResult,
+    middleware::authentication::JwtClaims,
+    testing::{
+        client::AureliaTestClient,
+        util::{get_tc, get_tc_with, get_tc_with_handlers},
+    },
+};
+
+fn health_cfg_fn(app: Application) -> Application {
+    app.with_health_routes()
+}
+
+fn without_oidc(cfg: &mut Configuration) {
+    cfg.application.auth.oidc.clear();
+}
+
+#[derive(Debug, Clone)]
+struct FailingCheckHandler {}
+
+#[async_trait]
+impl AppHandler for FailingCheckHandler {
+    async fn on_login_callback(
+        &self,
+        _claims: &mut JwtClaims,
+        _config: &Configuration,
+        _db: db::DB,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn readiness_checks(&self, _config: &Configuration, _db: db::DB) -> Vec<CheckResult> {
+        vec![
+            CheckResult::ok("cache"),
+            CheckResult::failed("queue", "Queue unreachable"),
+            CheckResult::failed("database", "Replica lagging"),
+        ]
+    }
+}
+
+#[tokio::test]
+async fn test_healthz() {
+    let c = get_tc::<_, AureliaTestClient>(health_cfg_fn).await;
+
+    let r = c.request_get(&c.uri("/healthz")).await.unwrap();
+    assert_eq!(r.status(), 200);
+
+    let body: serde_json::Value = r.json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+}
+
+#[tokio::test]
+async fn test_readyz() {
+    let c = get_tc_with::<_, _, AureliaTestClient>(health_cfg_fn, without_oidc).await;
+
+    let r = c.request_get(&c.uri("/readyz")).await.unwrap();
+    assert_eq!(r.status(), 200);
+
+    let body: serde_json::Value = r.json().await.unwrap();
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["checks"]["database"]["status"], "ok");
+    assert_eq!(body["checks"]["migrations"]["status"], "ok");
+}
+
+#[tokio::test]
+async fn test_readyz_unreachable_oidc_provider() {
+    let c = get_tc_with::<_, _, AureliaTestClient>(health_cfg_fn, |cfg| {
+        for provider in cfg.application.auth.oidc.values_mut() {
+            provider.issuer_url = "http://127.0.0.1:1".into();
+        }
+    })
+    .await;
+
+    let r = c.request_get(&c.uri("/readyz")).await.unwrap();
+    assert_eq!(r.status(), 503);
+
+    let body: serde_json::Value = r.json().await.unwrap();
+    assert_eq!(body["status"], "not_ready");
+    assert_eq!(body["checks"]["database"]["status"], "ok");
+    assert!(!c.configuration.application.auth.oidc.is_empty());
+    for provider_name in c.configuration.application.auth.oidc.keys() {
+        let check = &body["checks"][format!("oidc:{}", provider_name)];
+        assert_eq!(check["status"], "failed");
+        assert!(check.get("error").is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_readyz_custom_checks() {
+    let c = get_tc_with_handlers::<_, _, AureliaTestClient>(
+        health_cfg_fn,
+        without_oidc,
+        Box::new(FailingCheckHandler {}),
+    )
+    .await;
+
+    let r = c.request_get(&c.uri("/readyz")).await.unwrap();
+    assert_eq!(r.status(), 503);
+
+    let body: serde_json::Value = r.json().await.unwrap();
+    assert_eq!(body["status"], "not_ready");
+    assert_eq!(body["checks"]["app:cache"]["status"], "ok");
+    assert_eq!(body["checks"]["app:queue"]["status"], "failed");
+    // Errors are only logged.
+    assert!(body["checks"]["app:queue"].get("error").is_none());
+
+    // Custom checks don't replace the built-in ones of the same name.
+    assert_eq!(body["checks"]["database"]["status"], "ok");
+    assert_eq!(body["checks"]["app:database"]["status"], "failed");
+}
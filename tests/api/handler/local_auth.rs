@@ -0,0 +1,359 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use aurelia::{
+    anyhow,
+    application::AppHandler,
+    async_trait,
+    configuration::Configuration,
+    database::{
+        entity::{
+            local_credential::LocalCredential, oidc_session::OidcSession,
+            user_identity::UserIdentity,
+        },
+        init_connection,
+    },
+    driver::db,
+    middleware::authentication::JwtClaims,
+    serde_json::{json, Value},
+    testing::{
+        client::{AureliaTestClient, TestClient},
+        util::{get_tc, get_tc_with, get_tc_with_handlers},
+    },
+    uuid::Uuid,
+};
+use chrono::Utc;
+use futures::future::join_all;
+
+use crate::cfg_fn;
+
+fn enable_local(cfg: &mut Configuration) {
+    cfg.application.auth.local.enabled = true;
+}
+
+/// Keeps the delivered password reset token and the login failures, rejects
+/// logins of email addresses starting with `rejected`. Like an application,
+/// which stores its own user data, it requires the credential of the user to
+/// be visible.
+#[derive(Debug, Clone, Default)]
+struct LocalHandler {
+    reset_token: Arc<Mutex<Option<String>>>,
+    login_failures: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait]
+impl AppHandler for LocalHandler {
+    async fn on_login_callback(
+        &self,
+        claims: &mut JwtClaims,
+        _config: &Configuration,
+        db: db::DB,
+    ) -> anyhow::Result<()> {
+        if claims.email.starts_with("rejected") {
+            return Err(anyhow::anyhow!("Registration is closed"));
+        }
+        LocalCredential::get(claims.user_id()?, &db)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Unknown user: {}", claims.sub))?;
+        Ok(())
+    }
+
+    async fn on_password_reset_requested(
+        &self,
+        _credential: &LocalCredential,
+        token: &str,
+        _config: &Configuration,
+    ) -> anyhow::Result<()> {
+        *self.reset_token.lock().unwrap() = Some(token.into());
+        Ok(())
+    }
+
+    async fn on_login_failure(
+        &self,
+        provider_name: &str,
+        error: &str,
+        _config: &Configuration,
+        _db: db::DB,
+    ) -> anyhow::Result<()> {
+        self.login_failures
+            .lock()
+            .unwrap()
+            .push(format!("{}:{}", provider_name, error));
+        Ok(())
+    }
+}
+
+async fn identities_status(c: &AureliaTestClient) -> u16 {
+    let r = c.request_get(&c.uri("/auth/identities")).await.unwrap();
+    r.status().as_u16()
+}
+
+/// Links an identity of another provider with an OIDC session to the logged
+/// in user and returns the id of the session.
+async fn linked_session(c: &AureliaTestClient, db: &db::DB) -> Uuid {
+    let r = c.request_get(&c.uri("/auth/self")).await.unwrap();
+    let claims: Value = r.json().await.unwrap();
+    let user_id = Uuid::parse_str(claims["sub"].as_str().unwrap()).unwrap();
+    let subject = Uuid::new_v4().to_string();
+
+    let mut tx = db.begin().await.unwrap();
+    assert!(UserIdentity::link("mock", &subject, user_id, &mut tx)
+        .await
+        .unwrap());
+    tx.commit().await.unwrap();
+
+    let now = Utc::now();
+    let session = OidcSession {
+        id: Uuid::new_v4(),
+        provider_name: "mock".into(),
+        subject,
+        refresh_token: None,
+        id_token: None,
+        created_at: now,
+        expires_at: now + chrono::Duration::hours(1),
+    };
+    session.insert(db).await.unwrap();
+    session.id
+}
+
+#[tokio::test]
+async fn test_local_auth_disabled() {
+    let c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/login"),
+            &json!({"email": "user@example.com", "password": "secret123"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 404);
+}
+
+#[tokio::test]
+async fn test_local_register_login_and_change_password() {
+    let mut c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, enable_local).await;
+    let email = format!("{}@example.com", Uuid::new_v4());
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/register"),
+            &json!({"email": email, "password": "short"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 400);
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/register"),
+            &json!({"email": email, "password": "secret123"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 201);
+    assert!(r.headers().get("Set-Cookie").is_some());
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/register"),
+            &json!({"email": email, "password": "secret123"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 409);
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/login"),
+            &json!({"email": email, "password": "wrong-password"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 401);
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/login"),
+            &json!({"email": email, "password": "secret123"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 200);
+    let token: Value = r.json().await.unwrap();
+    c.set_jwt(token["token"].as_str().unwrap().to_string());
+
+    let r = c.request_get(&c.uri("/auth/self")).await.unwrap();
+    let claims: Value = r.json().await.unwrap();
+    assert_eq!(claims["email"], email.as_str());
+
+    // Only JWTs issued before the second of the change are revoked.
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/password"),
+            &json!({"current_password": "secret123", "new_password": "secret456"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 204);
+    assert_eq!(identities_status(&c).await, 401);
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/login"),
+            &json!({"email": email, "password": "secret456"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 200);
+}
+
+#[tokio::test]
+async fn test_local_password_reset() {
+    let c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, enable_local).await;
+
+    // Unknown users are not revealed
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/password_reset"),
+            &json!({"email": "unknown@example.com"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 202);
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/password_reset/confirm"),
+            &json!({"token": "invalid", "new_password": "secret456"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 400);
+}
+
+#[tokio::test]
+async fn test_local_password_reset_with_valid_token() {
+    let handler = LocalHandler::default();
+    let mut c = get_tc_with_handlers::<_, _, AureliaTestClient>(
+        cfg_fn,
+        enable_local,
+        Box::new(handler.clone()),
+    )
+    .await;
+    let db = init_connection(&c.configuration).await;
+    let email = format!("{}@example.com", Uuid::new_v4());
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/register"),
+            &json!({"email": email, "password": "secret123"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 201);
+    let token: Value = r.json().await.unwrap();
+    c.set_jwt(token["token"].as_str().unwrap().to_string());
+    let session = linked_session(&c, &db).await;
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/password_reset"),
+            &json!({"email": email}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 202);
+    let token = handler.reset_token.lock().unwrap().clone().unwrap();
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/password_reset/confirm"),
+            &json!({"token": token, "new_password": "secret456"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 204);
+
+    // Whoever holds the old credentials is logged out.
+    assert_eq!(identities_status(&c).await, 401);
+    assert!(OidcSession::get(session, &db).await.unwrap().is_none());
+
+    for (password, status) in [("secret123", 401), ("secret456", 200)] {
+        let r = c
+            .request_post_json(
+                &c.uri("/auth/local/login"),
+                &json!({"email": email, "password": password}),
+            )
+            .await
+            .unwrap();
+        assert_eq!(r.status(), status);
+    }
+
+    // Tokens can only be used once
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/password_reset/confirm"),
+            &json!({"token": token, "new_password": "secret789"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 400);
+}
+
+#[tokio::test]
+async fn test_local_register_rejected_by_callback() {
+    let handler = LocalHandler::default();
+    let c = get_tc_with_handlers::<_, _, AureliaTestClient>(
+        cfg_fn,
+        enable_local,
+        Box::new(handler.clone()),
+    )
+    .await;
+    let email = format!("rejected-{}@example.com", Uuid::new_v4());
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/register"),
+            &json!({"email": email, "password": "secret123"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 403);
+    assert_eq!(
+        *handler.login_failures.lock().unwrap(),
+        vec!["local:callback_rejected"]
+    );
+
+    // No account was left behind, which would reach the callback again.
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/local/login"),
+            &json!({"email": email, "password": "secret123"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 401);
+}
+
+#[tokio::test]
+async fn test_local_register_concurrently() {
+    let c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, enable_local).await;
+    let email = format!("{}@example.com", Uuid::new_v4());
+    let uri = c.uri("/auth/local/register");
+    let body = json!({"email": email, "password": "secret123"});
+
+    let responses = join_all((0..4).map(|_| c.request_post_json(&uri, &body))).await;
+    let mut statuses: Vec<u16> = responses
+        .into_iter()
+        .map(|r| r.unwrap().status().as_u16())
+        .collect();
+    statuses.sort_unstable();
+
+    assert_eq!(statuses, vec![201, 409, 409, 409]);
+}
@@ -1 +1,5 @@
+mod api_key;
 mod authentication;
+mod health;
+mod local_auth;
+mod well_known;
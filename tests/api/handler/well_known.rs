@@ -0,0 +1,71 @@
+use aurelia::testing::{
+    client::{AureliaTestClient, TestClient},
+    util::{get_tc, get_tc_with},
+};
+
+use crate::cfg_fn;
+
+#[tokio::test]
+async fn test_jwks() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+    c.authenticated().await;
+
+    let r = c
+        .request_get(&c.uri("/.well-known/jwks.json"))
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 200);
+
+    let jwks: serde_json::Value = r.json().await.unwrap();
+    let header = jsonwebtoken::decode_header(c.client_jwt.as_ref().unwrap()).unwrap();
+
+    assert!(jwks["keys"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|k| k["kid"].as_str() == header.kid.as_deref()));
+}
+
+#[tokio::test]
+async fn test_openid_configuration() {
+    let c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = c
+        .request_get(&c.uri("/.well-known/openid-configuration"))
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 200);
+
+    let cfg: serde_json::Value = r.json().await.unwrap();
+    assert!(cfg["jwks_uri"]
+        .as_str()
+        .unwrap()
+        .ends_with("/.well-known/jwks.json"));
+}
+
+#[tokio::test]
+async fn test_openid_configuration_publishes_issuer() {
+    let mut c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |cfg| {
+        cfg.application.auth.jwt_issuer = Some("https://auth.example.com".into());
+    })
+    .await;
+    c.authenticated().await;
+
+    let cfg: serde_json::Value = c
+        .request_get(&c.uri("/.well-known/openid-configuration"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(cfg["issuer"], "https://auth.example.com");
+
+    let claims: serde_json::Value = c
+        .request_get(&c.uri("/auth/self"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(claims["iss"], cfg["issuer"]);
+}
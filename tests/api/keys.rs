@@ -0,0 +1,247 @@
+use aurelia::{
+    cli::cli_retire_jwt_key,
+    configuration::{Configuration, JwtKey},
+    keys::{generate_keypair, Jwk, KeySet},
+    middleware::authentication::create_jwt,
+    serde_json::Value,
+    testing::{
+        client::{AureliaTestClient, TestClient},
+        util::get_tc_with,
+    },
+};
+use jsonwebtoken::Algorithm;
+use openssl::rsa::Rsa;
+
+use crate::cfg_fn;
+
+fn rsa_key(active: bool) -> JwtKey {
+    let rsa = Rsa::generate(2048).unwrap();
+    JwtKey {
+        kid: None,
+        private_key: Some(String::from_utf8(rsa.private_key_to_pem().unwrap()).unwrap()),
+        public_key: String::from_utf8(rsa.public_key_to_pem().unwrap()).unwrap(),
+        active,
+    }
+}
+
+fn kid(key: &JwtKey) -> String {
+    Jwk::from_public_key_pem(&key.public_key, "RS256")
+        .unwrap()
+        .kid
+}
+
+/// The legacy key of the test configuration is retired by the new key.
+fn rotated(cfg: &mut Configuration, new_key: JwtKey) {
+    cfg.application.auth.jwt_keys = vec![new_key];
+}
+
+async fn self_claims(c: &AureliaTestClient) -> Value {
+    let r = c.request_get(&c.uri("/auth/self")).await.unwrap();
+    r.json().await.unwrap()
+}
+
+fn header_kid(jwt: &str) -> Option<String> {
+    jsonwebtoken::decode_header(jwt).unwrap().kid
+}
+
+#[tokio::test]
+async fn test_retired_key_still_verifies() {
+    let new_key = rsa_key(true);
+    let mut c =
+        get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |cfg| rotated(cfg, new_key.clone())).await;
+
+    // Signed by the legacy key, before it was retired
+    c.configuration.application.auth.jwt_keys = vec![];
+    c.authenticated().await;
+    let legacy_kid = header_kid(c.client_jwt.as_ref().unwrap()).unwrap();
+    assert_ne!(legacy_kid, kid(&new_key));
+
+    assert!(!self_claims(&c).await.is_null());
+}
+
+#[tokio::test]
+async fn test_unknown_kid_is_rejected() {
+    let mut c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |_| {}).await;
+
+    // Signed by a key, which isn't part of the key set
+    c.configuration.application.auth.jwt_keys = vec![rsa_key(true)];
+    c.authenticated().await;
+
+    assert!(self_claims(&c).await.is_null());
+}
+
+#[tokio::test]
+async fn test_only_active_key_signs() {
+    let new_key = rsa_key(true);
+    let inactive_key = rsa_key(false);
+    let mut cfg = Configuration::new(None).unwrap();
+    cfg.application.auth.jwt_keys = vec![inactive_key.clone(), new_key.clone()];
+
+    let keys = KeySet::new(&cfg.application.auth).unwrap();
+    assert_eq!(keys.keys().len(), 3);
+    assert_eq!(keys.keys().iter().filter(|k| k.active).count(), 1);
+    assert_eq!(keys.signing_key().unwrap().0.kid, kid(&new_key));
+
+    let jwt = create_jwt(&cfg, None, Some(true)).await.unwrap();
+    assert_eq!(header_kid(&jwt), Some(kid(&new_key)));
+}
+
+#[tokio::test]
+async fn test_public_key_only_verifies() {
+    let mut cfg = Configuration::new(None).unwrap();
+    let jwt = create_jwt(&cfg, None, Some(true)).await.unwrap();
+
+    // E.g. a service, which only verifies JWTs issued elsewhere
+    cfg.application.auth.jwt_rsa_private_key = String::new();
+    let keys = KeySet::new(&cfg.application.auth).unwrap();
+    assert!(keys.signing_key().is_err());
+
+    let key = keys.verification_key(header_kid(&jwt).as_deref()).unwrap();
+    let validation = jsonwebtoken::Validation::new(key.algorithm);
+    assert!(jsonwebtoken::decode::<Value>(&jwt, key.decoding_key(), &validation).is_ok());
+}
+
+#[tokio::test]
+async fn test_multiple_active_keys_are_rejected() {
+    let mut cfg = Configuration::new(None).unwrap();
+    cfg.application.auth.jwt_keys = vec![rsa_key(true), rsa_key(true)];
+
+    assert!(KeySet::new(&cfg.application.auth).is_err());
+}
+
+#[tokio::test]
+async fn test_retire_jwt_key() {
+    let new_key = rsa_key(true);
+    let inactive_key = rsa_key(false);
+    let mut cfg = Configuration::new(None).unwrap();
+    cfg.application.auth.jwt_keys = vec![inactive_key.clone(), new_key.clone()];
+
+    assert!(cli_retire_jwt_key(&cfg, &kid(&inactive_key)).is_ok());
+    // The active key signs and the kid must exist
+    assert!(cli_retire_jwt_key(&cfg, &kid(&new_key)).is_err());
+    assert!(cli_retire_jwt_key(&cfg, "unknown").is_err());
+}
+
+fn generated_key(alg: Algorithm) -> JwtKey {
+    let (private_key, public_key) = generate_keypair(alg).unwrap();
+    JwtKey {
+        kid: None,
+        private_key: Some(private_key),
+        public_key,
+        active: true,
+    }
+}
+
+/// Signs with the given algorithm, either with the `jwt_secret` or with a
+/// new active key.
+async fn client_for(alg: Algorithm) -> AureliaTestClient {
+    get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |cfg| {
+        cfg.application.auth.jwt_algorithm = format!("{:?}", alg);
+        if alg == Algorithm::ES256 || alg == Algorithm::EdDSA {
+            cfg.application.auth.jwt_keys = vec![generated_key(alg)];
+        }
+    })
+    .await
+}
+
+async fn assert_round_trip(alg: Algorithm) {
+    let mut c = client_for(alg).await;
+    let sub = aurelia::uuid::Uuid::new_v4();
+    c.set_jwt(create_jwt(&c.configuration, Some(sub), None).await.unwrap());
+
+    let header = jsonwebtoken::decode_header(c.client_jwt.as_ref().unwrap()).unwrap();
+    assert_eq!(header.alg, alg);
+    assert_eq!(self_claims(&c).await["sub"], sub.to_string());
+}
+
+#[tokio::test]
+async fn test_round_trip_hs256() {
+    assert_round_trip(Algorithm::HS256).await;
+}
+
+#[tokio::test]
+async fn test_round_trip_rs256() {
+    assert_round_trip(Algorithm::RS256).await;
+}
+
+#[tokio::test]
+async fn test_round_trip_es256() {
+    assert_round_trip(Algorithm::ES256).await;
+}
+
+#[tokio::test]
+async fn test_round_trip_eddsa() {
+    assert_round_trip(Algorithm::EdDSA).await;
+}
+
+#[tokio::test]
+async fn test_active_key_signs_in_its_algorithm() {
+    // Configured for RSA, but the active key is an EC key
+    let mut c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |cfg| {
+        cfg.application.auth.jwt_keys = vec![generated_key(Algorithm::ES256)];
+    })
+    .await;
+    c.authenticated().await;
+
+    let header = jsonwebtoken::decode_header(c.client_jwt.as_ref().unwrap()).unwrap();
+    assert_eq!(header.alg, Algorithm::ES256);
+    assert!(!self_claims(&c).await.is_null());
+}
+
+async fn jwks(c: &AureliaTestClient) -> Vec<Value> {
+    let r = c
+        .request_get(&c.uri("/.well-known/jwks.json"))
+        .await
+        .unwrap();
+    let jwks: Value = r.json().await.unwrap();
+    jwks["keys"].as_array().unwrap().clone()
+}
+
+async fn active_jwk(alg: Algorithm) -> Value {
+    let mut c = client_for(alg).await;
+    c.authenticated().await;
+    let kid = header_kid(c.client_jwt.as_ref().unwrap()).unwrap();
+
+    jwks(&c)
+        .await
+        .into_iter()
+        .find(|k| k["kid"] == kid.as_str())
+        .expect("Signing key is not published")
+}
+
+#[tokio::test]
+async fn test_jwks_hs256_is_empty() {
+    let c = client_for(Algorithm::HS256).await;
+    assert!(jwks(&c).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_jwks_rsa() {
+    let jwk = active_jwk(Algorithm::RS256).await;
+    assert_eq!(jwk["kty"], "RSA");
+    assert_eq!(jwk["alg"], "RS256");
+    assert_eq!(jwk["use"], "sig");
+    assert!(jwk["n"].is_string());
+    assert_eq!(jwk["e"], "AQAB");
+}
+
+#[tokio::test]
+async fn test_jwks_ec() {
+    let jwk = active_jwk(Algorithm::ES256).await;
+    assert_eq!(jwk["kty"], "EC");
+    assert_eq!(jwk["alg"], "ES256");
+    assert_eq!(jwk["crv"], "P-256");
+    // 32 bytes, base64url encoded without padding
+    assert_eq!(jwk["x"].as_str().unwrap().len(), 43);
+    assert_eq!(jwk["y"].as_str().unwrap().len(), 43);
+}
+
+#[tokio::test]
+async fn test_jwks_ed25519() {
+    let jwk = active_jwk(Algorithm::EdDSA).await;
+    assert_eq!(jwk["kty"], "OKP");
+    assert_eq!(jwk["alg"], "EdDSA");
+    assert_eq!(jwk["crv"], "Ed25519");
+    assert_eq!(jwk["x"].as_str().unwrap().len(), 43);
+    assert!(jwk.get("y").is_none());
+}
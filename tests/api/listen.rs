@@ -0,0 +1,113 @@
+use std::{os::unix::fs::PermissionsExt, time::Duration};
+
+use aurelia::{
+    application::{Application, BoundAddress},
+    configuration::{Configuration, ListenAddress},
+    uuid::Uuid,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::cfg_fn;
+
+#[tokio::test]
+async fn test_multiple_listeners() {
+    let socket = std::env::temp_dir().join(format!("aurelia-{}.sock", Uuid::new_v4()));
+
+    let mut cfg = Configuration::new(None).unwrap();
+    cfg.http.port = 0;
+    cfg.http.listen = vec![
+        ListenAddress::try_from("::1".to_string()).unwrap(),
+        ListenAddress::try_from(format!("unix:{}", socket.display())).unwrap(),
+    ];
+    cfg.http.unix_socket_mode = Some("660".into());
+
+    let app = Application::init(Some(cfg), None).await.unwrap();
+    let prepared_app = cfg_fn(app).prepare().await.unwrap();
+    let local_addrs = prepared_app.local_addrs().to_vec();
+    assert_eq!(local_addrs.len(), 3);
+    assert_eq!(local_addrs[0].port(), Some(prepared_app.get_port()));
+    assert_eq!(local_addrs[2], BoundAddress::Unix(socket.clone()));
+    assert_eq!(
+        std::fs::metadata(&socket).unwrap().permissions().mode() & 0o777,
+        0o660
+    );
+
+    let shutdown = prepared_app.shutdown_handle();
+    let server = tokio::spawn(prepared_app.run());
+
+    for addr in &local_addrs[..2] {
+        let url = format!("http://{}/.well-known/openid-configuration", addr);
+        assert_eq!(reqwest::get(&url).await.unwrap().status(), 200);
+    }
+
+    let mut stream = tokio::net::UnixStream::connect(&socket).await.unwrap();
+    stream
+        .write_all(b"GET /.well-known/openid-configuration HTTP/1.0\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.0 200"));
+
+    shutdown.shutdown();
+    tokio::time::timeout(Duration::from_secs(10), server)
+        .await
+        .expect("Server didn't shut down")
+        .unwrap()
+        .unwrap();
+    assert!(!socket.exists());
+}
+
+#[test]
+fn test_listen_address() {
+    assert_eq!(
+        ListenAddress::try_from("[::1]:8080".to_string()).unwrap(),
+        ListenAddress::Tcp {
+            ip: "::1".parse().unwrap(),
+            port: Some(8080)
+        }
+    );
+    assert!(ListenAddress::try_from("localhost".to_string()).is_err());
+}
+
+#[tokio::test]
+async fn test_invalid_unix_socket_mode() {
+    let socket = std::env::temp_dir().join(format!("aurelia-{}.sock", Uuid::new_v4()));
+
+    let mut cfg = Configuration::new(None).unwrap();
+    cfg.http.port = 0;
+    cfg.http.listen = vec![ListenAddress::try_from(format!("unix:{}", socket.display())).unwrap()];
+    cfg.http.unix_socket_mode = Some("rw-rw----".into());
+
+    let app = Application::init(Some(cfg), None).await.unwrap();
+    assert!(cfg_fn(app).prepare().await.is_err());
+    // Nothing is bound with an invalid mode.
+    assert!(!socket.exists());
+    assert!(!socket.with_extension("sock.tmp").exists());
+}
+
+#[tokio::test]
+async fn test_with_listener() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut cfg = Configuration::new(None).unwrap();
+    cfg.http.port = addr.port();
+
+    let app = Application::init(Some(cfg), None).await.unwrap();
+    let prepared_app = cfg_fn(app).with_listener(listener).prepare().await.unwrap();
+    assert_eq!(prepared_app.local_addrs(), &[BoundAddress::Tcp(addr)]);
+
+    let shutdown = prepared_app.shutdown_handle();
+    let server = tokio::spawn(prepared_app.run());
+
+    let url = format!("http://{}/.well-known/openid-configuration", addr);
+    assert_eq!(reqwest::get(&url).await.unwrap().status(), 200);
+
+    shutdown.shutdown();
+    tokio::time::timeout(Duration::from_secs(10), server)
+        .await
+        .expect("Server didn't shut down")
+        .unwrap()
+        .unwrap();
+}
@@ -1,7 +1,20 @@
 use aurelia::application::Application;
 
+mod app_handler;
 mod handler;
+mod keys;
+#[cfg(unix)]
+mod listen;
+mod metrics;
+mod middleware;
+mod oidc_metadata;
+mod oidc_provider;
+mod return_to;
+mod revocation;
+mod shutdown;
+mod tls;
+mod user_identity;
 
 pub fn cfg_fn(app: Application) -> Application {
-    app.with_auth_routes()
+    app.with_auth_routes().with_well_known_routes()
 }
@@ -0,0 +1,111 @@
+use aurelia::{
+    application::Application,
+    testing::{
+        client::{AureliaTestClient, TestClient},
+        util::get_tc,
+    },
+};
+
+use crate::cfg_fn;
+
+fn metrics_cfg_fn(app: Application) -> Application {
+    cfg_fn(app).with_metrics()
+}
+
+async fn scrape(c: &AureliaTestClient) -> String {
+    let r = c.request_get(&c.uri("/metrics")).await.unwrap();
+    assert_eq!(r.status(), 200);
+    assert!(r.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+
+    r.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_metrics_http_requests_and_jwt_authentications() {
+    let mut c = get_tc::<_, AureliaTestClient>(metrics_cfg_fn).await;
+    let prefix = c.configuration.application.auth.path_prefix.clone();
+
+    c.authenticated().await;
+    let r = c
+        .request_get(&c.uri(&format!("{}/self", prefix)))
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 200);
+
+    c.set_jwt("not-a-jwt".into());
+    let r = c
+        .request_get(&c.uri(&format!("{}/identities", prefix)))
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 401);
+
+    let metrics = scrape(&c).await;
+    assert!(metrics.contains(&format!(
+        "aurelia_http_requests_total{{method=\"GET\",route=\"{}/self\",status=\"200\"}} 1",
+        prefix
+    )));
+    assert!(metrics.contains(&format!(
+        "aurelia_http_request_duration_seconds_count{{method=\"GET\",route=\"{}/identities\",status=\"401\"}} 1",
+        prefix
+    )));
+    assert!(metrics.contains(&format!(
+        "aurelia_http_request_duration_seconds_bucket{{method=\"GET\",route=\"{}/self\",status=\"200\",le=\"+Inf\"}} 1",
+        prefix
+    )));
+    assert!(metrics.contains("aurelia_jwt_authentications_total{result=\"success\"} 1"));
+    assert!(metrics.contains(
+        "aurelia_jwt_authentications_total{result=\"failure\",reason=\"invalid_token\"} 1"
+    ));
+    assert!(metrics.contains("# TYPE aurelia_db_pool_connections gauge"));
+    assert!(metrics.contains("aurelia_db_pool_idle_connections "));
+}
+
+#[tokio::test]
+async fn test_metrics_oidc_logins() {
+    let c = get_tc::<_, AureliaTestClient>(metrics_cfg_fn).await;
+    let prefix = c.configuration.application.auth.path_prefix.clone();
+    let provider_name = c
+        .configuration
+        .application
+        .auth
+        .oidc
+        .keys()
+        .next()
+        .unwrap()
+        .clone();
+
+    // Without a login state cookie.
+    let r = c
+        .request_get(&c.uri(&format!(
+            "{}/oidc_login_cb/{}?state=abc&code=def",
+            prefix, provider_name
+        )))
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 302);
+
+    let r = c
+        .request_get(&c.uri(&format!("{}/oidc_login_cb/not-configured", prefix)))
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 302);
+
+    let metrics = scrape(&c).await;
+    assert!(metrics.contains(&format!(
+        "aurelia_oidc_logins_total{{provider=\"{}\",result=\"invalid_state\"}} 1",
+        provider_name
+    )));
+    assert!(metrics.contains("aurelia_oidc_logins_total{provider=\"unknown\","));
+    assert!(!metrics.contains("not-configured"));
+}
+
+#[tokio::test]
+async fn test_metrics_disabled() {
+    let c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = c.request_get(&c.uri("/metrics")).await.unwrap();
+    assert_eq!(r.status(), 404);
+}
@@ -0,0 +1,78 @@
+use aurelia::{
+    middleware::authentication::JwtClaims,
+    serde_json::{json, Value},
+    testing::{
+        client::{AureliaTestClient, TestClient},
+        util::{get_tc, get_tc_with},
+    },
+};
+
+use crate::cfg_fn;
+
+async fn self_claims(c: &AureliaTestClient) -> Value {
+    let r = c.request_get(&c.uri("/auth/self")).await.unwrap();
+    r.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_registered_claims() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+    c.authenticated().await;
+
+    let claims = self_claims(&c).await;
+    // Defaults to the base url of the running application
+    assert_eq!(claims["iss"], format!("http://localhost:{}", c.port));
+    assert!(claims["iat"].is_number());
+    assert!(claims["nbf"].is_number());
+    assert!(claims["jti"].is_string());
+}
+
+#[tokio::test]
+async fn test_foreign_issuer_is_rejected() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    // Same keys, but minted for another environment
+    c.configuration.application.auth.jwt_issuer = Some("https://staging.example.com".into());
+    c.authenticated().await;
+
+    assert!(self_claims(&c).await.is_null());
+}
+
+#[tokio::test]
+async fn test_audience_is_validated() {
+    let mut c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |cfg| {
+        cfg.application.auth.jwt_audience = Some("production".into());
+    })
+    .await;
+
+    c.authenticated().await;
+    assert_eq!(self_claims(&c).await["aud"], "production");
+
+    c.configuration.application.auth.jwt_audience = Some("staging".into());
+    c.authenticated().await;
+    assert!(self_claims(&c).await.is_null());
+}
+
+#[tokio::test]
+async fn test_extra_claims() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let mut claims = c.claims("user");
+    claims.set_extra("tenant_id", 42).unwrap();
+    claims.set_extra("features", vec!["beta"]).unwrap();
+    assert!(claims.set_extra("sub", "admin").is_err());
+    assert_eq!(claims.extra::<u32>("tenant_id"), Some(42));
+    assert_eq!(claims.extra::<String>("tenant_id"), None);
+
+    c.authenticated_with_claims(claims);
+    let self_claims = self_claims(&c).await;
+    assert_eq!(self_claims["sub"], "user");
+    assert_eq!(self_claims["tenant_id"], 42);
+    assert_eq!(self_claims["features"], json!(["beta"]));
+
+    let claims: JwtClaims = aurelia::serde_json::from_value(self_claims).unwrap();
+    assert_eq!(
+        claims.extra::<Vec<String>>("features"),
+        Some(vec!["beta".into()])
+    );
+}
@@ -0,0 +1,152 @@
+use aurelia::{
+    application::Application,
+    axum::{extract::Extension, middleware, routing::get, Router},
+    middleware::{
+        authentication::{jwt_auth_middleware, JwtClaims},
+        authorization::{require_permission, require_role},
+    },
+    serde_json::{json, Value},
+    testing::{
+        client::{AureliaTestClient, TestClient},
+        util::get_tc,
+    },
+};
+
+fn cfg_fn(app: Application) -> Application {
+    let cfg = app.state.configuration.application.clone();
+    let routes = Router::new()
+        .route(
+            "/admin",
+            get(|| async { "admin" }).route_layer(middleware::from_fn(|req, next| {
+                require_role(req, next, "admin")
+            })),
+        )
+        .route(
+            "/recipes",
+            get(|| async { "recipes" }).route_layer(middleware::from_fn(|req, next| {
+                require_permission(req, next, "recipe:write")
+            })),
+        )
+        .route_layer(middleware::from_fn(move |req, next| {
+            jwt_auth_middleware(req, next, cfg.clone())
+        }))
+        .layer(Extension(None::<JwtClaims>));
+
+    // Without `jwt_auth_middleware`, the route layers authenticate themselves.
+    let plain_routes = Router::new()
+        .route(
+            "/plain/admin",
+            get(|| async { "admin" }).route_layer(middleware::from_fn(|req, next| {
+                require_role(req, next, "admin")
+            })),
+        )
+        .route(
+            "/plain/recipes",
+            get(|| async { "recipes" }).route_layer(middleware::from_fn(|req, next| {
+                require_permission(req, next, "recipe:write")
+            })),
+        );
+
+    app.with_extra_routes(routes.merge(plain_routes))
+}
+
+fn cfg_fn_with_auth_routes(app: Application) -> Application {
+    cfg_fn(app).with_auth_routes()
+}
+
+#[tokio::test]
+async fn test_require_role() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = c.request_get(&c.uri("/admin")).await.unwrap();
+    assert_eq!(r.status(), 401);
+
+    c.authenticated().await;
+    let r = c.request_get(&c.uri("/admin")).await.unwrap();
+    assert_eq!(r.status(), 403);
+    assert!(r.text().await.unwrap().contains("Missing role 'admin'"));
+
+    let mut claims = c.claims("user");
+    claims.roles = vec!["admin".into()];
+    c.authenticated_with_claims(claims);
+    let r = c.request_get(&c.uri("/admin")).await.unwrap();
+    assert_eq!(r.status(), 200);
+}
+
+#[tokio::test]
+async fn test_require_permission() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let mut claims = c.claims("user");
+    claims.roles = vec!["admin".into()];
+    c.authenticated_with_claims(claims.clone());
+    let r = c.request_get(&c.uri("/recipes")).await.unwrap();
+    assert_eq!(r.status(), 403);
+    assert!(r
+        .text()
+        .await
+        .unwrap()
+        .contains("Missing permission 'recipe:write'"));
+
+    claims.permissions = vec!["recipe:write".into()];
+    c.authenticated_with_claims(claims);
+    let r = c.request_get(&c.uri("/recipes")).await.unwrap();
+    assert_eq!(r.status(), 200);
+}
+
+#[tokio::test]
+async fn test_require_role_without_jwt_auth_middleware() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = c.request_get(&c.uri("/plain/admin")).await.unwrap();
+    assert_eq!(r.status(), 401);
+
+    let mut claims = c.claims("user");
+    claims.roles = vec!["admin".into()];
+    c.authenticated_with_claims(claims);
+    let r = c.request_get(&c.uri("/plain/admin")).await.unwrap();
+    assert_eq!(r.status(), 200);
+
+    // The JWT cookie works as well
+    let r = aurelia::reqwest::Client::new()
+        .get(c.uri("/plain/admin"))
+        .header(
+            "Cookie",
+            format!(
+                "{}={}",
+                c.configuration.application.auth.jwt_cookie_name,
+                c.client_jwt.clone().unwrap()
+            ),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 200);
+}
+
+#[tokio::test]
+async fn test_require_permission_with_api_key() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn_with_auth_routes).await;
+    let mut claims = c.claims("user");
+    claims.permissions = vec!["recipe:write".into()];
+    c.authenticated_with_claims(claims);
+
+    let r = c
+        .request_post_json(
+            &c.uri("/auth/api_keys"),
+            &json!({"name": "ci", "scopes": ["recipe:write"]}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 201);
+    let created: Value = r.json().await.unwrap();
+
+    let mut kc = get_tc::<_, AureliaTestClient>(cfg_fn_with_auth_routes).await;
+    kc.set_jwt(created["key"].as_str().unwrap().to_string());
+    let r = kc.request_get(&kc.uri("/plain/recipes")).await.unwrap();
+    assert_eq!(r.status(), 200);
+    let r = kc.request_get(&kc.uri("/recipes")).await.unwrap();
+    assert_eq!(r.status(), 200);
+    let r = kc.request_get(&kc.uri("/plain/admin")).await.unwrap();
+    assert_eq!(r.status(), 403);
+}
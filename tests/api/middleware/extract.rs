@@ -0,0 +1,90 @@
+use aurelia::{
+    application::Application,
+    axum::{routing::get, Json, Router},
+    middleware::extract::{AuthUser, Claims, MaybeUser},
+    reqwest,
+    serde_json::Value,
+    testing::{
+        client::{AureliaTestClient, TestClient},
+        util::get_tc,
+    },
+};
+
+fn cfg_fn(app: Application) -> Application {
+    let routes = Router::new()
+        .route("/me", get(|AuthUser(claims)| async move { claims.sub }))
+        .route(
+            "/maybe",
+            get(|MaybeUser(claims)| async move {
+                claims.map_or_else(|| "anonymous".to_string(), |c| c.sub)
+            }),
+        )
+        .route(
+            "/claims",
+            get(|Claims(claims): Claims<Value>| async move { Json(claims) }),
+        );
+
+    app.with_extra_routes(routes)
+}
+
+#[tokio::test]
+async fn test_auth_user() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = c.request_get(&c.uri("/me")).await.unwrap();
+    assert_eq!(r.status(), 401);
+
+    c.authenticated().await;
+    let r = c.request_get(&c.uri("/me")).await.unwrap();
+    assert_eq!(r.status(), 200);
+    assert!(!r.text().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_auth_user_redirects_browsers() {
+    let c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(c.uri("/me"))
+        .header("Accept", "text/html,application/xhtml+xml")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 303);
+    assert!(r.headers()["location"]
+        .to_str()
+        .unwrap()
+        .starts_with(&c.configuration.application.auth.login_path));
+}
+
+#[tokio::test]
+async fn test_maybe_user() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = c.request_get(&c.uri("/maybe")).await.unwrap();
+    assert_eq!(r.status(), 200);
+    assert_eq!(r.text().await.unwrap(), "anonymous");
+
+    c.authenticated().await;
+    let r = c.request_get(&c.uri("/maybe")).await.unwrap();
+    assert_eq!(r.status(), 200);
+    assert_ne!(r.text().await.unwrap(), "anonymous");
+}
+
+#[tokio::test]
+async fn test_claims() {
+    let mut c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+
+    let r = c.request_get(&c.uri("/claims")).await.unwrap();
+    assert_eq!(r.status(), 401);
+
+    c.authenticated().await;
+    let r = c.request_get(&c.uri("/claims")).await.unwrap();
+    assert_eq!(r.status(), 200);
+    let claims: Value = r.json().await.unwrap();
+    assert!(claims["sub"].is_string());
+    assert!(claims["exp"].is_number());
+}
@@ -0,0 +1,3 @@
+mod authentication;
+mod authorization;
+mod extract;
@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+use aurelia::{
+    configuration::{Configuration, Oidc},
+    driver::oidc::OidcMetadataCache,
+};
+use futures::future::join_all;
+
+use crate::oidc_provider::{self, MockProvider, PROVIDER};
+
+const TTL: Duration = Duration::from_millis(500);
+
+fn provider(mock: &MockProvider) -> Oidc {
+    let mut cfg = Configuration::new(None).unwrap();
+    oidc_provider::configure(&mut cfg, &mock.issuer_url);
+    cfg.get_oidc_provider(PROVIDER).unwrap()
+}
+
+async fn wait_for_discoveries(mock: &MockProvider, count: usize) {
+    for _ in 0..50 {
+        if mock.discoveries() >= count {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("Expected {} discoveries, got {}", count, mock.discoveries());
+}
+
+#[tokio::test]
+async fn test_metadata_cache() {
+    let mock = oidc_provider::spawn();
+    let provider = provider(&mock);
+    let cache = OidcMetadataCache::new(TTL);
+
+    // Discovered on the first use, then served from the cache
+    cache.get(&provider).await.unwrap();
+    cache.get(&provider).await.unwrap();
+    assert_eq!(mock.discoveries(), 1);
+
+    // Stale entries are served, while a single refresh runs in the background.
+    tokio::time::sleep(TTL).await;
+    let stale = join_all((0..4).map(|_| cache.get(&provider))).await;
+    assert!(stale.iter().all(|m| m.is_ok()));
+    wait_for_discoveries(&mock, 2).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(mock.discoveries(), 2);
+}
+
+#[tokio::test]
+async fn test_metadata_cache_issuer_unreachable() {
+    let mock = oidc_provider::spawn();
+    let provider = provider(&mock);
+    let cache = OidcMetadataCache::new(TTL);
+    cache.get(&provider).await.unwrap();
+
+    mock.set_unreachable(true);
+    tokio::time::sleep(TTL).await;
+
+    // The stale entry doesn't wait for the issuer.
+    let start = Instant::now();
+    let metadata = cache.get(&provider).await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(100));
+    assert_eq!(metadata.issuer().as_str(), mock.issuer_url);
+    assert_eq!(mock.discoveries(), 1);
+}
@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use aurelia::{
+    axum::{
+        extract::Form,
+        routing::{get, post},
+        Extension, Json, Router,
+    },
+    configuration::Configuration,
+    keys::Jwk,
+    serde_json::{json, Value},
+    uuid::Uuid,
+};
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use openssl::rsa::Rsa;
+
+/// Name of the mocked provider in the configuration.
+pub const PROVIDER: &str = "mock";
+
+/// Client id of the application at the mocked provider.
+pub const CLIENT_ID: &str = "aurelia-test";
+
+/// Email address of the user, who logs in at the mocked provider.
+pub const EMAIL: &str = "mock@example.com";
+
+/// Refresh token, which the token endpoint hands out on every refresh.
+pub const ROTATED_REFRESH_TOKEN: &str = "rotated-refresh-token";
+
+/// Handle of a running mock provider.
+#[derive(Clone)]
+pub struct MockProvider {
+    pub issuer_url: String,
+    /// Subject of the user, who logs in.
+    pub subject: String,
+    discoveries: Arc<AtomicUsize>,
+    unreachable: Arc<AtomicBool>,
+    token_requests: Arc<Mutex<Vec<HashMap<String, String>>>>,
+    nonce: Arc<Mutex<Option<String>>>,
+    /// PEM encoded private key and JWK of the ID token signing key
+    signing_key: Arc<(String, Jwk)>,
+}
+
+impl MockProvider {
+    /// Nonce of the issued ID tokens, usually the one of the authorization
+    /// request.
+    pub fn set_nonce(&self, nonce: Option<String>) {
+        *self.nonce.lock().unwrap() = nonce;
+    }
+
+    /// Number of served discovery documents.
+    pub fn discoveries(&self) -> usize {
+        self.discoveries.load(Ordering::SeqCst)
+    }
+
+    /// Form parameters of all requests to the token endpoint.
+    pub fn token_requests(&self) -> Vec<HashMap<String, String>> {
+        self.token_requests.lock().unwrap().clone()
+    }
+
+    /// An unreachable issuer never answers the discovery.
+    pub fn set_unreachable(&self, unreachable: bool) {
+        self.unreachable.store(unreachable, Ordering::SeqCst);
+    }
+}
+
+async fn discovery(Extension(mock): Extension<MockProvider>) -> Json<Value> {
+    if mock.unreachable.load(Ordering::SeqCst) {
+        std::future::pending::<()>().await;
+    }
+    mock.discoveries.fetch_add(1, Ordering::SeqCst);
+
+    let issuer = &mock.issuer_url;
+    Json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/authorize", issuer),
+        "token_endpoint": format!("{}/token", issuer),
+        "jwks_uri": format!("{}/jwks", issuer),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+    }))
+}
+
+async fn jwks(Extension(mock): Extension<MockProvider>) -> Json<Value> {
+    Json(json!({ "keys": [mock.signing_key.1] }))
+}
+
+fn id_token(mock: &MockProvider) -> String {
+    let (private_key, jwk) = mock.signing_key.as_ref();
+    let now = Utc::now().timestamp();
+    let claims = json!({
+        "iss": mock.issuer_url,
+        "sub": mock.subject,
+        "aud": CLIENT_ID,
+        "exp": now + 300,
+        "iat": now,
+        "nonce": *mock.nonce.lock().unwrap(),
+        "email": EMAIL,
+    });
+
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(jwk.kid.clone());
+    let key = EncodingKey::from_rsa_pem(private_key.as_bytes()).unwrap();
+    jsonwebtoken::encode(&header, &claims, &key).unwrap()
+}
+
+/// Accepts every authorization code and refresh token, the latter is rotated.
+/// Code exchanges get a signed ID token.
+async fn token(
+    Extension(mock): Extension<MockProvider>,
+    Form(request): Form<HashMap<String, String>>,
+) -> Json<Value> {
+    let mut response = json!({
+        "access_token": "access-token",
+        "token_type": "bearer",
+        "expires_in": 3600,
+        "refresh_token": ROTATED_REFRESH_TOKEN,
+    });
+    if request.get("grant_type").map(String::as_str) == Some("authorization_code") {
+        response["id_token"] = json!(id_token(&mock));
+    }
+    mock.token_requests.lock().unwrap().push(request);
+
+    Json(response)
+}
+
+/// Spawns an OpenID Connect provider, which serves discovery, its JWKS and
+/// a token endpoint for code exchanges and refreshes.
+pub fn spawn() -> MockProvider {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let rsa = Rsa::generate(2048).unwrap();
+    let private_key = String::from_utf8(rsa.private_key_to_pem().unwrap()).unwrap();
+    let public_key = String::from_utf8(rsa.public_key_to_pem().unwrap()).unwrap();
+
+    let mock = MockProvider {
+        issuer_url: format!("http://{}", listener.local_addr().unwrap()),
+        subject: Uuid::new_v4().to_string(),
+        discoveries: Default::default(),
+        unreachable: Default::default(),
+        token_requests: Default::default(),
+        nonce: Default::default(),
+        signing_key: Arc::new((
+            private_key,
+            Jwk::from_public_key_pem(&public_key, "RS256").unwrap(),
+        )),
+    };
+
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/jwks", get(jwks))
+        .route("/token", post(token))
+        .layer(Extension(mock.clone()));
+    let server = aurelia::axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(app.into_make_service());
+    tokio::spawn(server);
+
+    mock
+}
+
+/// Adds the provider `PROVIDER` for the mock running at `issuer_url`.
+pub fn configure(cfg: &mut Configuration, issuer_url: &str) {
+    let mut provider = cfg.get_oidc_provider("auth0").unwrap();
+    provider.provider_name = PROVIDER.into();
+    provider.client_id = CLIENT_ID.into();
+    provider.issuer_url = issuer_url.into();
+    provider.redirect_url = format!("http://localhost:3001/auth/oidc_login_cb/{}", PROVIDER);
+    cfg.application.auth.oidc.insert(PROVIDER.into(), provider);
+}
@@ -0,0 +1,30 @@
+use aurelia::{configuration::Configuration, return_to::is_allowed};
+
+#[test]
+fn test_relative_return_to() {
+    let mut cfg = Configuration::new(None).unwrap();
+    cfg.application.auth.return_to_allowlist = vec!["/app".into()];
+    let auth = &cfg.application.auth;
+
+    assert!(is_allowed(auth, "/app"));
+    assert!(is_allowed(auth, "/app/deep/link?x=1#top"));
+    assert!(!is_allowed(auth, "/application"));
+    assert!(!is_allowed(auth, "/app/../admin"));
+    assert!(!is_allowed(auth, "//evil.example.com/app"));
+    assert!(!is_allowed(auth, "/\\evil.example.com/app"));
+    assert!(!is_allowed(auth, "https://evil.example.com/app"));
+}
+
+#[test]
+fn test_absolute_return_to() {
+    let mut cfg = Configuration::new(None).unwrap();
+    cfg.application.auth.return_to_allowlist = vec!["https://app.example.com/".into()];
+    let auth = &cfg.application.auth;
+
+    assert!(is_allowed(auth, "https://app.example.com/deep/link"));
+    assert!(!is_allowed(auth, "http://app.example.com/deep/link"));
+    assert!(!is_allowed(auth, "https://app.example.com.evil.com/"));
+    assert!(!is_allowed(auth, "https://evil.com@app.example.com/"));
+    assert!(!is_allowed(auth, "/deep/link"));
+    assert!(!is_allowed(auth, "javascript:alert(1)"));
+}
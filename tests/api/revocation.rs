@@ -0,0 +1,187 @@
+use std::time::Duration;
+
+use aurelia::{
+    database::{
+        entity::{
+            oidc_session::OidcSession,
+            token_revocation::{RevokedSubject, RevokedToken},
+        },
+        init_connection,
+    },
+    driver::{db, revocation::RevocationList},
+    serde_json::Value,
+    testing::{client::AureliaTestClient, util::get_tc_with},
+    uuid::Uuid,
+};
+use chrono::Utc;
+
+use crate::cfg_fn;
+
+async fn self_claims(c: &AureliaTestClient) -> Value {
+    let r = c.request_get(&c.uri("/auth/self")).await.unwrap();
+    r.json().await.unwrap()
+}
+
+async fn count(db: &db::DB, sql: &str, key: &str) -> i64 {
+    sqlx::query_scalar(sql)
+        .bind(key)
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_revoke_subject() {
+    let mut c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |cfg| {
+        cfg.application.auth.revocation_reload_seconds = 1;
+    })
+    .await;
+    let db = init_connection(&c.configuration).await;
+    let sub = Uuid::new_v4().to_string();
+
+    c.authenticated_with_claims(c.claims(&sub));
+    let old_jwt = c.client_jwt.clone().unwrap();
+    assert_eq!(self_claims(&c).await["sub"], sub.as_str());
+
+    // Only JWTs issued before the second of the revocation are revoked.
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    RevocationList::new(Duration::from_secs(1))
+        .revoke_subject(&sub, &c.configuration.application.auth, &db)
+        .await
+        .unwrap();
+
+    // A fresh login right afterwards
+    c.authenticated_with_claims(c.claims(&sub));
+    let fresh_jwt = c.client_jwt.clone().unwrap();
+
+    // The revocation becomes effective with the next reload.
+    c.client_jwt = Some(old_jwt);
+    let mut revoked = false;
+    for _ in 0..50 {
+        if self_claims(&c).await.is_null() {
+            revoked = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(revoked);
+
+    c.client_jwt = Some(fresh_jwt);
+    assert_eq!(self_claims(&c).await["sub"], sub.as_str());
+}
+
+#[tokio::test]
+async fn test_revoke_subject_within_second() {
+    let c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |_| {}).await;
+    let db = init_connection(&c.configuration).await;
+    let sub = Uuid::new_v4().to_string();
+
+    let revocations = RevocationList::new(Duration::from_secs(30));
+    revocations
+        .revoke_subject(&sub, &c.configuration.application.auth, &db)
+        .await
+        .unwrap();
+    let revoked_before = RevokedSubject::list_valid(&db)
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|s| s.subject == sub)
+        .unwrap()
+        .revoked_before
+        .timestamp() as usize;
+
+    let mut jwt = c.claims(&sub);
+    jwt.iat = Some(revoked_before - 1);
+    assert!(revocations.is_revoked(&jwt).await);
+
+    jwt.iat = Some(revoked_before);
+    assert!(!revocations.is_revoked(&jwt).await);
+
+    jwt.iat = None;
+    assert!(revocations.is_revoked(&jwt).await);
+
+    let mut other = c.claims(&Uuid::new_v4().to_string());
+    other.iat = Some(revoked_before - 1);
+    assert!(!revocations.is_revoked(&other).await);
+}
+
+#[tokio::test]
+async fn test_delete_expired_revocations() {
+    let c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |_| {}).await;
+    let db = init_connection(&c.configuration).await;
+    let now = Utc::now();
+
+    let token = |expires_at| RevokedToken {
+        jti: Uuid::new_v4().to_string(),
+        subject: "user".into(),
+        expires_at,
+    };
+    let subject = |expires_at| RevokedSubject {
+        subject: Uuid::new_v4().to_string(),
+        revoked_before: now,
+        expires_at,
+    };
+    let (expired_token, valid_token) = (
+        token(now - chrono::Duration::hours(1)),
+        token(now + chrono::Duration::hours(1)),
+    );
+    let (expired_subject, valid_subject) = (
+        subject(now - chrono::Duration::hours(1)),
+        subject(now + chrono::Duration::hours(1)),
+    );
+    for t in [&expired_token, &valid_token] {
+        t.insert(&db).await.unwrap();
+    }
+    for s in [&expired_subject, &valid_subject] {
+        s.upsert(&db).await.unwrap();
+    }
+
+    RevokedToken::delete_expired(&db).await.unwrap();
+    RevokedSubject::delete_expired(&db).await.unwrap();
+
+    let tokens = "SELECT COUNT(*) FROM revoked_token WHERE jti = $1";
+    assert_eq!(count(&db, tokens, &expired_token.jti).await, 0);
+    assert_eq!(count(&db, tokens, &valid_token.jti).await, 1);
+
+    let subjects = "SELECT COUNT(*) FROM revoked_subject WHERE subject = $1";
+    assert_eq!(count(&db, subjects, &expired_subject.subject).await, 0);
+    assert_eq!(count(&db, subjects, &valid_subject.subject).await, 1);
+}
+
+#[tokio::test]
+async fn test_refresh_deletes_expired_oidc_sessions() {
+    let c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |cfg| {
+        cfg.application.auth.revocation_reload_seconds = 1;
+    })
+    .await;
+    let db = init_connection(&c.configuration).await;
+    let now = Utc::now();
+
+    let session = |expires_at| OidcSession {
+        id: Uuid::new_v4(),
+        provider_name: "mock".into(),
+        subject: Uuid::new_v4().to_string(),
+        refresh_token: None,
+        id_token: None,
+        created_at: now - chrono::Duration::hours(2),
+        expires_at,
+    };
+    let (expired, valid) = (
+        session(now - chrono::Duration::hours(1)),
+        session(now + chrono::Duration::hours(1)),
+    );
+    for s in [&expired, &valid] {
+        s.insert(&db).await.unwrap();
+    }
+
+    let mut deleted = false;
+    for _ in 0..50 {
+        if OidcSession::get(expired.id, &db).await.unwrap().is_none() {
+            deleted = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(deleted);
+    assert!(OidcSession::get(valid.id, &db).await.unwrap().is_some());
+}
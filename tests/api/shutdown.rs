@@ -0,0 +1,29 @@
+use std::time::Duration;
+
+use aurelia::{application::Application, configuration::Configuration};
+
+use crate::cfg_fn;
+
+#[tokio::test]
+async fn test_shutdown_handle_stops_server() {
+    let mut cfg = Configuration::new(None).unwrap();
+    cfg.http.port = 0;
+
+    let app = Application::init(Some(cfg), None).await.unwrap();
+    let prepared_app = cfg_fn(app).prepare().await.unwrap();
+    let port = prepared_app.get_port();
+    let shutdown = prepared_app.shutdown_handle();
+    let server = tokio::spawn(prepared_app.run());
+
+    let url = format!("http://localhost:{}/.well-known/jwks.json", port);
+    assert_eq!(reqwest::get(&url).await.unwrap().status(), 200);
+
+    shutdown.shutdown();
+    tokio::time::timeout(Duration::from_secs(10), server)
+        .await
+        .expect("Server didn't shut down")
+        .unwrap()
+        .unwrap();
+
+    assert!(reqwest::get(&url).await.is_err());
+}
@@ -0,0 +1,201 @@
+use std::{net::TcpStream, path::Path, time::Duration};
+
+use aurelia::{
+    configuration::{Configuration, Tls},
+    serde_json::{json, Value},
+    testing::{client::AureliaTestClient, util::get_tc_with},
+    uuid::Uuid,
+};
+use openssl::{
+    asn1::Asn1Time,
+    hash::MessageDigest,
+    pkey::PKey,
+    rsa::Rsa,
+    ssl::{SslConnector, SslMethod, SslVerifyMode},
+    x509::X509NameBuilder,
+    x509::X509,
+};
+
+use crate::cfg_fn;
+
+/// Writes a self-signed certificate for `localhost` into `dir`.
+fn write_certificate(dir: &Path) {
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "localhost").unwrap();
+    let name = name.build();
+
+    let mut cert = X509::builder().unwrap();
+    cert.set_version(2).unwrap();
+    cert.set_subject_name(&name).unwrap();
+    cert.set_issuer_name(&name).unwrap();
+    cert.set_pubkey(&key).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+    std::fs::write(dir.join("cert.pem"), cert.build().to_pem().unwrap()).unwrap();
+    std::fs::write(dir.join("key.pem"), key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+}
+
+/// Writes a self-signed certificate for `localhost` and returns the paths
+/// of certificate and key.
+fn self_signed_certificate() -> (String, String) {
+    let dir = std::env::temp_dir().join(format!("aurelia-tls-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    write_certificate(&dir);
+
+    (
+        dir.join("cert.pem").to_string_lossy().into(),
+        dir.join("key.pem").to_string_lossy().into(),
+    )
+}
+
+fn enable_tls(cfg: &mut Configuration) {
+    let (cert_path, key_path) = self_signed_certificate();
+    cfg.http.tls = Some(Tls {
+        cert_path,
+        key_path,
+        reload_seconds: 60,
+        redirect_port: None,
+    });
+}
+
+/// SHA-256 fingerprint of the certificate served on `port`.
+async fn served_certificate(port: u16) -> Vec<u8> {
+    tokio::task::spawn_blocking(move || {
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        let stream = TcpStream::connect(("localhost", port)).unwrap();
+        let stream = connector.build().connect("localhost", stream).unwrap();
+        stream
+            .ssl()
+            .peer_certificate()
+            .unwrap()
+            .digest(MessageDigest::sha256())
+            .unwrap()
+            .to_vec()
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_https() {
+    let c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |cfg| {
+        enable_tls(cfg);
+        cfg.application.auth.local.enabled = true;
+    })
+    .await;
+    assert!(c.configuration.http.full_base_url().starts_with("https://"));
+
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let r = client
+        .get(format!(
+            "https://localhost:{}/.well-known/openid-configuration",
+            c.port
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 200);
+    let _: Value = r.json().await.unwrap();
+
+    // The JWT cookie is secure, even though `jwt_cookie_secure` is off.
+    assert!(!c.configuration.application.auth.jwt_cookie_secure);
+    let r = client
+        .post(format!("https://localhost:{}/auth/local/register", c.port))
+        .json(&json!({"email": format!("{}@example.com", Uuid::new_v4()), "password": "secret123"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 201);
+    let cookie = r.headers()["set-cookie"].to_str().unwrap().to_string();
+    assert!(cookie.starts_with(&c.configuration.application.auth.jwt_cookie_name));
+    assert!(cookie.contains("; secure"));
+
+    let token: Value = r.json().await.unwrap();
+    let claims: Value = client
+        .get(format!("https://localhost:{}/auth/self", c.port))
+        .bearer_auth(token["token"].as_str().unwrap())
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(claims["iss"], format!("https://localhost:{}", c.port));
+
+    // No plain HTTP on the HTTPS port
+    assert!(reqwest::get(format!(
+        "http://localhost:{}/.well-known/openid-configuration",
+        c.port
+    ))
+    .await
+    .is_err());
+}
+
+#[tokio::test]
+async fn test_http_redirects_to_https() {
+    let redirect_port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |cfg| {
+        enable_tls(cfg);
+        cfg.http.tls.as_mut().unwrap().redirect_port = Some(redirect_port);
+    })
+    .await;
+
+    let r = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("http://localhost:{}/deep/link?a=b", redirect_port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 308);
+    assert_eq!(
+        r.headers()["location"],
+        format!("https://localhost:{}/deep/link?a=b", c.port).as_str()
+    );
+}
+
+#[tokio::test]
+async fn test_certificate_reload() {
+    let c = get_tc_with::<_, _, AureliaTestClient>(cfg_fn, |cfg| {
+        enable_tls(cfg);
+        cfg.http.tls.as_mut().unwrap().reload_seconds = 1;
+    })
+    .await;
+    let tls = c.configuration.http.tls.as_ref().unwrap();
+    let initial = served_certificate(c.port).await;
+
+    // E.g. a renewal
+    write_certificate(Path::new(&tls.cert_path).parent().unwrap());
+    let renewed = std::fs::read(&tls.cert_path).unwrap();
+    let renewed = X509::from_pem(&renewed)
+        .unwrap()
+        .digest(MessageDigest::sha256())
+        .unwrap()
+        .to_vec();
+    assert_ne!(initial, renewed);
+
+    let mut served = initial;
+    for _ in 0..50 {
+        served = served_certificate(c.port).await;
+        if served == renewed {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(served, renewed);
+}
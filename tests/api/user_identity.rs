@@ -0,0 +1,126 @@
+use aurelia::{
+    database::{entity::user_identity::UserIdentity, init_connection},
+    driver::db,
+    testing::{client::AureliaTestClient, util::get_tc},
+    uuid::Uuid,
+};
+
+use crate::cfg_fn;
+
+async fn db() -> db::DB {
+    // Spawning the application migrates the database.
+    let c = get_tc::<_, AureliaTestClient>(cfg_fn).await;
+    init_connection(&c.configuration).await
+}
+
+fn subject() -> String {
+    Uuid::new_v4().to_string()
+}
+
+#[tokio::test]
+async fn test_get_or_create() {
+    let db = db().await;
+    let (google, github) = (subject(), subject());
+
+    let mut tx = db.begin().await.unwrap();
+    let identity = UserIdentity::get_or_create("google", &google, &mut tx)
+        .await
+        .unwrap();
+    let again = UserIdentity::get_or_create("google", &google, &mut tx)
+        .await
+        .unwrap();
+    let other = UserIdentity::get_or_create("github", &github, &mut tx)
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    assert_eq!(identity.user_id, again.user_id);
+    assert_ne!(identity.user_id, other.user_id);
+    assert_eq!(
+        UserIdentity::get("google", &google, &db)
+            .await
+            .unwrap()
+            .unwrap()
+            .user_id,
+        identity.user_id
+    );
+}
+
+#[tokio::test]
+async fn test_link_second_provider() {
+    let db = db().await;
+    let (google, github) = (subject(), subject());
+
+    let mut tx = db.begin().await.unwrap();
+    let user_id = UserIdentity::get_or_create("google", &google, &mut tx)
+        .await
+        .unwrap()
+        .user_id;
+    assert!(UserIdentity::link("github", &github, user_id, &mut tx)
+        .await
+        .unwrap());
+    // Linking again is a no-op
+    assert!(UserIdentity::link("github", &github, user_id, &mut tx)
+        .await
+        .unwrap());
+    tx.commit().await.unwrap();
+
+    let identities = UserIdentity::list_for_user(user_id, &db).await.unwrap();
+    let providers: Vec<_> = identities
+        .iter()
+        .map(|i| i.provider_name.as_str())
+        .collect();
+    assert_eq!(providers, vec!["google", "github"]);
+
+    // A login with the linked provider resolves to the same user.
+    let mut tx = db.begin().await.unwrap();
+    let identity = UserIdentity::get_or_create("github", &github, &mut tx)
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+    assert_eq!(identity.user_id, user_id);
+}
+
+#[tokio::test]
+async fn test_link_identity_already_linked() {
+    let db = db().await;
+    let github = subject();
+
+    let mut tx = db.begin().await.unwrap();
+    let owner = UserIdentity::get_or_create("github", &github, &mut tx)
+        .await
+        .unwrap()
+        .user_id;
+    assert!(
+        !UserIdentity::link("github", &github, Uuid::new_v4(), &mut tx)
+            .await
+            .unwrap()
+    );
+    tx.commit().await.unwrap();
+
+    let identity = UserIdentity::get("github", &github, &db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(identity.user_id, owner);
+}
+
+#[tokio::test]
+async fn test_link_is_rolled_back() {
+    let db = db().await;
+    let github = subject();
+
+    // E.g. the login is rejected by the `on_login_callback`
+    let mut tx = db.begin().await.unwrap();
+    assert!(
+        UserIdentity::link("github", &github, Uuid::new_v4(), &mut tx)
+            .await
+            .unwrap()
+    );
+    tx.rollback().await.unwrap();
+
+    assert!(UserIdentity::get("github", &github, &db)
+        .await
+        .unwrap()
+        .is_none());
+}